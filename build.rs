@@ -12,4 +12,24 @@ fn main() {
     // write to version
     std::fs::create_dir_all("target/release").unwrap();
     std::fs::write("target/release/version", sha.trim()).unwrap();
+
+    // expose to the crate, for reporting in health
+    println!("cargo:rustc-env=BLINK_CMP_GIT_SHA={}", sha.trim());
+
+    // rerun when checking out another commit or committing, which moves HEAD or the branch it
+    // points to
+    let mut git_paths = vec![".git/HEAD".to_string(), ".git/packed-refs".to_string()];
+    if let Some(head_ref) = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        git_paths.push(format!(".git/{}", head_ref));
+    }
+    // missing paths would rerun on every build
+    for path in git_paths
+        .iter()
+        .filter(|path| std::path::Path::new(path).exists())
+    {
+        println!("cargo:rerun-if-changed={}", path);
+    }
 }
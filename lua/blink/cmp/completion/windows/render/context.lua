@@ -25,7 +25,8 @@ function draw_context.get_from_items(context, draw, items)
     context.get_line(),
     context.get_cursor()[2],
     vim.tbl_map(function(item) return item.label end, items),
-    require('blink.cmp.config').completion.keyword.range
+    require('blink.cmp.config').completion.keyword.range,
+    items
   )
 
   local ctxs = {}
@@ -5,6 +5,11 @@
 --- @field use_unsafe_no_lock boolean (deprecated) alias for frecency.unsafe_no_lock, will be removed in v2.0
 --- @field use_proximity boolean Boosts the score of items matching nearby words. Note, this does not apply when using the Lua implementation.
 --- @field sorts blink.cmp.Sort[] Controls which sorts to use and in which order.
+--- @field deprecated_score_offset number Added to the score of deprecated items (via the `deprecated` field or the `Deprecated` tag), typically negative to demote them. Note, this does not apply when using the Lua implementation.
+--- @field filter? blink.cmp.FuzzyFilterOptions Skips items by kind, deprecation or label and drops low scoring matches. Note, this does not apply when using the Lua implementation.
+--- @field dedup? blink.cmp.FuzzyDedupOptions Collapses items sharing the same key, such as the same label from the buffer and LSP sources, into a single item. The merged items are available via `item.duplicates`. Note, this does not apply when using the Lua implementation.
+--- @field max_items? number Maximum number of items retained across all providers and buffers, evicting the least recently used providers when exceeded. Unlimited by default. Note, this does not apply when using the Lua implementation.
+--- @field max_bytes? number Same as `max_items` for the approximate memory used by the items, in bytes. Unlimited by default. Note, this does not apply when using the Lua implementation.
 --- @field frecency blink.cmp.FuzzyFrecencyConfig Tracks the most recently/frequently used items and boosts the score of the item. Note, this does not apply when using the Lua implementation.
 --- @field prebuilt_binaries blink.cmp.PrebuiltBinariesConfig
 
@@ -32,7 +37,7 @@
 --- | 'lua' Always use the Lua implementation
 
 --- @alias blink.cmp.SortFunction fun(a: blink.cmp.CompletionItem, b: blink.cmp.CompletionItem): boolean | nil
---- @alias blink.cmp.Sort ("label" | "sort_text" | "kind" | "score" | "exact" | blink.cmp.SortFunction)
+--- @alias blink.cmp.Sort ("label" | "sort_text" | "kind" | "score" | "exact" | "deprecated" | "preselect" | blink.cmp.SortFunction)
 
 local validate = require('blink.cmp.config.utils').validate
 
@@ -43,6 +48,11 @@ local fuzzy = {
     max_typos = function(keyword) return math.floor(#keyword / 4) end,
     use_proximity = true,
     sorts = { 'score', 'sort_text' },
+    deprecated_score_offset = 0,
+    filter = nil,
+    dedup = nil,
+    max_items = nil,
+    max_bytes = nil,
     frecency = {
       enabled = true,
       path = vim.fn.stdpath('state') .. '/blink/cmp/frecency.dat',
@@ -92,7 +102,7 @@ function fuzzy.validate(config)
         if type(sorts) ~= 'table' then return false end
         for _, sort in ipairs(sorts) do
           if
-            not vim.tbl_contains({ 'label', 'sort_text', 'kind', 'score', 'exact' }, sort)
+            not vim.tbl_contains({ 'label', 'sort_text', 'kind', 'score', 'exact', 'deprecated', 'preselect' }, sort)
             and type(sort) ~= 'function'
           then
             return false
@@ -100,8 +110,13 @@ function fuzzy.validate(config)
         end
         return true
       end,
-      'one of: "label", "sort_text", "kind", "score", "exact" or a function',
+      'one of: "label", "sort_text", "kind", "score", "exact", "deprecated", "preselect" or a function',
     },
+    deprecated_score_offset = { config.deprecated_score_offset, 'number' },
+    filter = { config.filter, { 'table', 'nil' } },
+    dedup = { config.dedup, { 'table', 'nil' } },
+    max_items = { config.max_items, { 'number', 'nil' } },
+    max_bytes = { config.max_bytes, { 'number', 'nil' } },
     frecency = { config.frecency, 'table' },
     prebuilt_binaries = { config.prebuilt_binaries, 'table' },
   }, config)
@@ -6,15 +6,110 @@ local fuzzy = {
   implementation_type = 'lua',
   --- @type blink.cmp.FuzzyImplementation
   implementation = require('blink.cmp.fuzzy.lua'),
+  --- @type table<string, table<string, blink.cmp.CompletionItem[]>> Haystacks by namespace, then provider id
   haystacks_by_provider_cache = {},
   has_init_db = false,
 }
 
+--- Namespace for the provider items of the current context, so that the cmdline types and buffers
+--- don't overwrite each other's items and switching between them doesn't set the items again
+--- @return string
+local function get_namespace()
+  if vim.api.nvim_get_mode().mode == 'c' then return 'cmdline:' .. vim.fn.getcmdtype() end
+  return 'buffer:' .. vim.api.nvim_get_current_buf()
+end
+
+--- Keyword class to use for the current context, falling back to the default for the cmdline
+--- @return string?
+local function get_keyword_class()
+  if vim.api.nvim_get_mode().mode == 'c' then return end
+  return vim.bo.filetype
+end
+
+--- Encodings of the `textEdit` columns by LSP client id, matching `text_edits.offset_encoding_from_item`.
+--- Items without a client (i.e. snippets and paths) always use utf-8
+--- @return table<number, 'utf-8' | 'utf-16' | 'utf-32'>
+local function get_offset_encodings()
+  local offset_encodings = {}
+  for _, client in ipairs(vim.lsp.get_clients({ bufnr = 0 })) do
+    offset_encodings[client.id] = client.offset_encoding
+  end
+  return offset_encodings
+end
+
+--- Buffers whose words are indexed by the Rust implementation, kept up to date via `nvim_buf_attach`.
+--- Mapped to a token per attachment, so that a stale attachment detaches instead of updating the index again
+--- @type table<number, table>
+local indexed_bufnrs = {}
+--- Buffers larger than this (in bytes) get the nearby words from the lines around the cursor instead
+local max_indexed_buffer_size = 1000000
+
+--- Indexes the words of the buffer and updates the changed lines on every change, so that getting
+--- the nearby words doesn't tokenize the lines around the cursor on every keystroke
+--- @param bufnr number
+local function index_buffer(bufnr)
+  if indexed_bufnrs[bufnr] or fuzzy.implementation_type ~= 'rust' then return end
+  local line_count = vim.api.nvim_buf_line_count(bufnr)
+  if vim.api.nvim_buf_get_offset(bufnr, line_count) > max_indexed_buffer_size then return end
+
+  local token = {}
+  local function set_lines()
+    fuzzy.implementation.set_buffer_lines(bufnr, vim.api.nvim_buf_get_lines(bufnr, 0, -1, false))
+  end
+  local attached = vim.api.nvim_buf_attach(bufnr, false, {
+    on_lines = function(_, _, _, first_row, old_last_row, new_last_row)
+      -- detach after switching to the Lua implementation or clearing the index
+      if indexed_bufnrs[bufnr] ~= token or fuzzy.implementation_type ~= 'rust' then return true end
+
+      local lines = vim.api.nvim_buf_get_lines(bufnr, first_row, new_last_row, false)
+      -- the index may have been reset after a panic, in which case we index the whole buffer again
+      if not pcall(fuzzy.implementation.update_buffer_lines, bufnr, first_row, old_last_row, lines) then set_lines() end
+    end,
+    on_reload = function()
+      if indexed_bufnrs[bufnr] == token then set_lines() end
+    end,
+    on_detach = function()
+      if indexed_bufnrs[bufnr] == token then fuzzy.clear_buffer_index(bufnr) end
+    end,
+  })
+  if not attached then return end
+
+  set_lines()
+  indexed_bufnrs[bufnr] = token
+end
+
+--- Gets the words around the cursor, along with their distance in lines from the cursor line
+--- @return table<string, number>
+local function get_nearby_words()
+  local bufnr = vim.api.nvim_get_current_buf()
+  local cursor_row = vim.api.nvim_win_get_cursor(0)[1]
+
+  index_buffer(bufnr)
+  if indexed_bufnrs[bufnr] then
+    local nearby_words = fuzzy.implementation.get_buffer_nearby_words(bufnr, cursor_row - 1, 30)
+    if nearby_words ~= nil then return nearby_words end
+  end
+
+  local start_row = math.max(0, cursor_row - 30)
+  local end_row = math.min(cursor_row + 30, vim.api.nvim_buf_line_count(bufnr))
+  local nearby_lines = vim.api.nvim_buf_get_lines(bufnr, start_row, end_row, false)
+  local nearby_text_length = 0
+  for _, nearby_line in ipairs(nearby_lines) do
+    nearby_text_length = nearby_text_length + #nearby_line + 1
+  end
+  if nearby_text_length >= 10000 then return {} end
+  return fuzzy.implementation.get_nearby_words(nearby_lines, cursor_row - 1 - start_row)
+end
+
 --- @param implementation 'lua' | 'rust'
 function fuzzy.set_implementation(implementation)
   assert(implementation == 'lua' or implementation == 'rust', 'Invalid fuzzy implementation: ' .. implementation)
   fuzzy.implementation_type = implementation
   fuzzy.implementation = require('blink.cmp.fuzzy.' .. implementation)
+
+  if implementation == 'rust' then
+    fuzzy.implementation.set_item_limits({ max_items = config.fuzzy.max_items, max_bytes = config.fuzzy.max_bytes })
+  end
 end
 
 function fuzzy.init_db()
@@ -76,8 +171,74 @@ function fuzzy.get_words(lines) return fuzzy.implementation.get_words(lines) end
 --- @param cursor_col number
 --- @param haystack string[]
 --- @param range blink.cmp.CompletionKeywordRange
-function fuzzy.fuzzy_matched_indices(line, cursor_col, haystack, range)
-  return fuzzy.implementation.fuzzy_matched_indices(line, cursor_col, haystack, range == 'full')
+--- @param items? blink.cmp.CompletionItem[] Items of the haystack, so that their `textEdit` ranges are used as the needle
+function fuzzy.fuzzy_matched_indices(line, cursor_col, haystack, range, items)
+  return fuzzy.implementation.fuzzy_matched_indices(
+    line,
+    cursor_col,
+    haystack,
+    range == 'full',
+    get_keyword_class(),
+    items,
+    items and get_offset_encodings() or nil
+  )
+end
+
+--- Removes the items at the 0-indexed positions from the provider's uploaded items, after the caller removed
+--- them from `haystack`, so that the next `fuzzy` call with `haystack` doesn't upload all of the items again
+--- @param provider_id string
+--- @param haystack blink.cmp.CompletionItem[] The provider's items, with the items already removed
+--- @param indices number[]
+function fuzzy.remove_provider_items(provider_id, haystack, indices)
+  local namespace = get_namespace()
+  local haystacks_cache = fuzzy.haystacks_by_provider_cache[namespace]
+  -- not uploaded yet, so the next `fuzzy` call uploads the whole haystack
+  if haystacks_cache == nil or haystacks_cache[provider_id] == nil then return end
+
+  local ok = pcall(fuzzy.implementation.remove_provider_items, provider_id, indices, namespace)
+  -- upload the whole haystack on the next `fuzzy` call when the items are gone, i.e. evicted
+  -- or reset after a panic
+  haystacks_cache[provider_id] = ok and haystack or nil
+end
+
+--- Replaces the items at the 0-indexed positions in the provider's uploaded items, after the caller replaced
+--- them in `haystack`, so that the next `fuzzy` call with `haystack` doesn't upload all of the items again
+--- @param provider_id string
+--- @param haystack blink.cmp.CompletionItem[] The provider's items, with the items already replaced
+--- @param patches table<number, blink.cmp.CompletionItem>
+function fuzzy.patch_provider_items(provider_id, haystack, patches)
+  local namespace = get_namespace()
+  local haystacks_cache = fuzzy.haystacks_by_provider_cache[namespace]
+  if haystacks_cache == nil or haystacks_cache[provider_id] == nil then return end
+
+  local ok = pcall(fuzzy.implementation.patch_provider_items, provider_id, patches, namespace)
+  -- upload the whole haystack on the next `fuzzy` call when the items are gone or the patches
+  -- were out of bounds
+  haystacks_cache[provider_id] = ok and haystack or nil
+end
+
+--- Clears the items of the provider, so that the next `fuzzy` call sets them again
+--- @param provider_id string
+--- @param namespace? string Defaults to the namespace of the current context
+function fuzzy.clear_provider_items(provider_id, namespace)
+  namespace = namespace or get_namespace()
+  fuzzy.implementation.clear_provider_items(provider_id, namespace)
+  local haystacks_cache = fuzzy.haystacks_by_provider_cache[namespace]
+  if haystacks_cache ~= nil then haystacks_cache[provider_id] = nil end
+end
+
+--- Clears the items of all providers in the namespace
+--- @param namespace? string Defaults to the namespace of the current context
+function fuzzy.clear_namespace(namespace)
+  namespace = namespace or get_namespace()
+  fuzzy.implementation.clear_namespace(namespace)
+  fuzzy.haystacks_by_provider_cache[namespace] = nil
+end
+
+--- Clears the items of all providers in all namespaces
+function fuzzy.clear_all()
+  fuzzy.implementation.clear_all()
+  fuzzy.haystacks_by_provider_cache = {}
 end
 
 --- @param line string
@@ -88,20 +249,21 @@ end
 function fuzzy.fuzzy(line, cursor_col, haystacks_by_provider, range)
   if config.fuzzy.frecency.enabled then fuzzy.init_db() end
 
-  for provider_id, haystack in pairs(haystacks_by_provider) do
-    -- set the provider items once since Lua <-> Rust takes the majority of the time
-    if fuzzy.haystacks_by_provider_cache[provider_id] ~= haystack then
-      fuzzy.haystacks_by_provider_cache[provider_id] = haystack
-      fuzzy.implementation.set_provider_items(provider_id, haystack)
+  local namespace = get_namespace()
+  fuzzy.haystacks_by_provider_cache[namespace] = fuzzy.haystacks_by_provider_cache[namespace] or {}
+  local haystacks_cache = fuzzy.haystacks_by_provider_cache[namespace]
+  local function set_provider_items()
+    for provider_id, haystack in pairs(haystacks_by_provider) do
+      -- set the provider items once since Lua <-> Rust takes the majority of the time
+      if haystacks_cache[provider_id] ~= haystack then
+        haystacks_cache[provider_id] = haystack
+        fuzzy.implementation.set_provider_items(provider_id, haystack, namespace)
+      end
     end
   end
+  set_provider_items()
 
-  -- get the nearby words
-  local cursor_row = vim.api.nvim_win_get_cursor(0)[1]
-  local start_row = math.max(0, cursor_row - 30)
-  local end_row = math.min(cursor_row + 30, vim.api.nvim_buf_line_count(0))
-  local nearby_text = table.concat(vim.api.nvim_buf_get_lines(0, start_row, end_row, false), '\n')
-  local nearby_words = #nearby_text < 10000 and fuzzy.implementation.get_words(nearby_text) or {}
+  local nearby_words = get_nearby_words()
 
   -- get the keyword
   local keyword_start_col, keyword_end_col = fuzzy.get_keyword_range(line, cursor_col, config.completion.keyword.range)
@@ -121,15 +283,41 @@ function fuzzy.fuzzy(line, cursor_col, haystacks_by_provider, range)
 
   -- perform fuzzy search
   local provider_ids = vim.tbl_keys(haystacks_by_provider)
-  local provider_idxs, matched_indices, scores, exacts = fuzzy.implementation.fuzzy(line, cursor_col, provider_ids, {
+  local fuzzy_opts = {
     max_typos = max_typos,
     use_frecency = config.fuzzy.frecency.enabled and keyword_length > 0,
     use_proximity = config.fuzzy.use_proximity and keyword_length > 0,
     nearby_words = nearby_words,
     match_suffix = range == 'full',
     snippet_score_offset = config.snippets.score_offset,
+    deprecated_score_offset = config.fuzzy.deprecated_score_offset,
+    filter = config.fuzzy.filter,
     sorts = sort_in_rust and sorts_list or nil,
-  })
+    keyword = get_keyword_class(),
+    offset_encodings = get_offset_encodings(),
+    dedup = fuzzy.implementation_type == 'rust' and config.fuzzy.dedup or nil,
+    namespace = namespace,
+  }
+  local function match()
+    if fuzzy.implementation_type ~= 'rust' or not jit then
+      return fuzzy.implementation.fuzzy(line, cursor_col, provider_ids, fuzzy_opts)
+    end
+    -- decode the matches from a single string via ffi, rather than converting each value separately
+    local matches, merged = fuzzy.implementation.fuzzy_packed(line, cursor_col, provider_ids, fuzzy_opts)
+    local provider_idxs, matched_indices, scores, exacts =
+      require('blink.cmp.fuzzy.rust.packed').decode_matches(matches)
+    return provider_idxs, matched_indices, scores, exacts, merged
+  end
+  local ok, provider_idxs, matched_indices, scores, exacts, merged = pcall(match)
+  if not ok then
+    -- the items were evicted (i.e. by `set_item_limits`), cleared or reset after a panic since they were set,
+    -- so set them again
+    if fuzzy.implementation.error_info(provider_idxs).code ~= 'fuzzy_before_set_items' then error(provider_idxs, 0) end
+    fuzzy.haystacks_by_provider_cache[namespace] = {}
+    haystacks_cache = fuzzy.haystacks_by_provider_cache[namespace]
+    set_provider_items()
+    provider_idxs, matched_indices, scores, exacts, merged = match()
+  end
 
   -- add items to the final list
   local filtered_items = {}
@@ -141,6 +329,15 @@ function fuzzy.fuzzy(line, cursor_col, haystacks_by_provider, range)
     item.score = scores[idx]
     item.exact = exacts[idx]
 
+    -- items which were collapsed into this item by deduplication
+    item.duplicates = nil
+    if merged then
+      item.duplicates = {}
+      for _, mtch in ipairs(merged[idx]) do
+        table.insert(item.duplicates, haystacks_by_provider[provider_ids[mtch.provider_idx + 1]][mtch.index + 1])
+      end
+    end
+
     table.insert(filtered_items, item)
   end
 
@@ -153,7 +350,7 @@ end
 --- @param range? blink.cmp.CompletionKeywordRange
 --- @return number, number
 function fuzzy.get_keyword_range(line, col, range)
-  return fuzzy.implementation.get_keyword_range(line, col, range == 'full')
+  return fuzzy.implementation.get_keyword_range(line, col, range == 'full', get_keyword_class())
 end
 
 function fuzzy.is_keyword_character(char)
@@ -170,7 +367,31 @@ end
 --- @param range blink.cmp.CompletionKeywordRange
 --- @return number, number
 function fuzzy.guess_edit_range(item, line, col, range)
-  return fuzzy.implementation.guess_edit_range(item, line, col, range == 'full')
+  return fuzzy.implementation.guess_edit_range(item, line, col, range == 'full', get_keyword_class())
+end
+
+--- Registers the characters making up a keyword for the filetype. Note, this does not apply when using the Lua implementation.
+--- @param filetype string
+--- @param keyword_class blink.cmp.FuzzyKeywordClass
+function fuzzy.register_keyword_class(filetype, keyword_class)
+  if fuzzy.implementation_type ~= 'rust' then return end
+  fuzzy.implementation.register_keyword_class(filetype, keyword_class)
 end
 
+--- Drops the word index of the buffer, which detaches from the buffer on its next change
+--- @param bufnr number
+function fuzzy.clear_buffer_index(bufnr)
+  if not indexed_bufnrs[bufnr] then return end
+  indexed_bufnrs[bufnr] = nil
+  if fuzzy.implementation_type == 'rust' then fuzzy.implementation.clear_buffer_lines(bufnr) end
+end
+
+vim.api.nvim_create_autocmd({ 'BufDelete', 'BufWipeout' }, {
+  desc = 'Clear the provider items and word index of the buffer when the buffer is deleted',
+  callback = function(args)
+    fuzzy.clear_namespace('buffer:' .. args.buf)
+    fuzzy.clear_buffer_index(args.buf)
+  end,
+})
+
 return fuzzy
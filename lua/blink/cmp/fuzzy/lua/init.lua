@@ -6,6 +6,7 @@ local guess_keyword_range = require('blink.cmp.fuzzy.lua.keyword').guess_keyword
 --- @type blink.cmp.FuzzyImplementation
 --- @diagnostic disable-next-line: missing-fields
 local fuzzy = {
+  --- Items by namespace and provider id, see `get_key`
   --- @type table<string, blink.cmp.CompletionItem[]>
   provider_items = {},
 }
@@ -13,6 +14,7 @@ local fuzzy = {
 function fuzzy.init_db() end
 function fuzzy.destroy_db() end
 function fuzzy.access() end
+function fuzzy.register_keyword_class() end
 
 local words_regex = vim.regex(
   [[\%(-\?\d\+\%(\.\d\+\)\?\|\h\%(\w\|á\|Á\|é\|É\|í\|Í\|ó\|Ó\|ú\|Ú\)*\%(-\%(\w\|á\|Á\|é\|É\|í\|Í\|ó\|Ó\|ú\|Ú\)*\)*\)]]
@@ -37,7 +39,75 @@ function fuzzy.get_words(text)
   return vim.tbl_keys(words)
 end
 
-function fuzzy.set_provider_items(provider_id, items) fuzzy.provider_items[provider_id] = items end
+function fuzzy.get_nearby_words(lines, cursor_idx)
+  local distance_by_word = {}
+  for idx, line in ipairs(lines) do
+    local distance = math.abs(idx - 1 - cursor_idx)
+    for _, word in ipairs(fuzzy.get_words(line)) do
+      if distance_by_word[word] == nil or distance < distance_by_word[word] then distance_by_word[word] = distance end
+    end
+  end
+  return distance_by_word
+end
+
+--- @param provider_id string
+--- @param namespace? string
+--- @return string
+local function get_key(provider_id, namespace) return (namespace or 'default') .. '\0' .. provider_id end
+
+function fuzzy.set_provider_items(provider_id, items, namespace)
+  fuzzy.provider_items[get_key(provider_id, namespace)] = items
+end
+
+--- Copies the provider's items, since the list passed to `set_provider_items` is owned by the caller
+--- @param provider_id string
+--- @param namespace? string
+--- @return blink.cmp.CompletionItem[]
+local function copy_provider_items(provider_id, namespace)
+  local items = fuzzy.provider_items[get_key(provider_id, namespace)]
+  assert(items ~= nil, 'Attempted to update the items of provider ' .. provider_id .. ' before setting them')
+  return vim.list_slice(items)
+end
+
+function fuzzy.append_provider_items(provider_id, items, namespace)
+  local key = get_key(provider_id, namespace)
+  fuzzy.provider_items[key] = vim.list_extend(vim.list_slice(fuzzy.provider_items[key] or {}), items)
+end
+
+function fuzzy.remove_provider_items(provider_id, indices, namespace)
+  local removed = {}
+  for _, idx in ipairs(indices) do
+    removed[idx + 1] = true
+  end
+
+  local items = {}
+  for idx, item in ipairs(copy_provider_items(provider_id, namespace)) do
+    if not removed[idx] then table.insert(items, item) end
+  end
+  fuzzy.provider_items[get_key(provider_id, namespace)] = items
+end
+
+function fuzzy.patch_provider_items(provider_id, patches, namespace)
+  local items = copy_provider_items(provider_id, namespace)
+  for idx in pairs(patches) do
+    assert(idx < #items, 'Item index ' .. idx .. ' is out of bounds for provider ' .. provider_id)
+  end
+  for idx, item in pairs(patches) do
+    items[idx + 1] = item
+  end
+  fuzzy.provider_items[get_key(provider_id, namespace)] = items
+end
+
+function fuzzy.clear_provider_items(provider_id, namespace) fuzzy.provider_items[get_key(provider_id, namespace)] = nil end
+
+function fuzzy.clear_namespace(namespace)
+  local prefix = namespace .. '\0'
+  for key in pairs(fuzzy.provider_items) do
+    if vim.startswith(key, prefix) then fuzzy.provider_items[key] = nil end
+  end
+end
+
+function fuzzy.clear_all() fuzzy.provider_items = {} end
 
 function fuzzy.fuzzy(line, cursor_col, provider_ids, opts)
   assert(opts.sorts == nil, 'Sorting is not supported in the Lua implementation')
@@ -50,7 +120,7 @@ function fuzzy.fuzzy(line, cursor_col, provider_ids, opts)
   local scores = {}
   local exacts = {}
   for provider_idx, provider_id in ipairs(provider_ids) do
-    for idx, item in ipairs(fuzzy.provider_items[provider_id] or {}) do
+    for idx, item in ipairs(fuzzy.provider_items[get_key(provider_id, opts.namespace)] or {}) do
       local score, exact = match(keyword, item.filterText or item.label)
 
       if score ~= nil then
@@ -70,6 +140,30 @@ function fuzzy.fuzzy(line, cursor_col, provider_ids, opts)
   return provider_idxs, matched_indices, scores, exacts
 end
 
+function fuzzy.filter(needle, strings, opts)
+  opts = opts or {}
+
+  local matches = {}
+  for idx, str in ipairs(strings) do
+    local score, exact = match(needle, str)
+    if score ~= nil then table.insert(matches, { index = idx - 1, score = score, exact = exact }) end
+  end
+  table.sort(matches, function(a, b)
+    if a.score ~= b.score then return a.score > b.score end
+    return a.index < b.index
+  end)
+
+  local indices, scores, exacts = {}, {}, {}
+  local matched_indices = opts.matched_indices and {} or nil
+  for _, mtch in ipairs(matches) do
+    table.insert(indices, mtch.index)
+    table.insert(scores, mtch.score)
+    table.insert(exacts, mtch.exact)
+    if matched_indices then table.insert(matched_indices, match_indices(needle, strings[mtch.index + 1])) end
+  end
+  return indices, scores, exacts, matched_indices
+end
+
 function fuzzy.fuzzy_matched_indices(line, cursor_col, haystack, match_suffix)
   local keyword_start, keyword_end = get_keyword_range(line, cursor_col, match_suffix)
   local keyword = line:sub(keyword_start + 1, keyword_end)
@@ -104,4 +198,20 @@ function fuzzy.guess_edit_range(item, line, col, match_suffix)
   return ranges[1][1], ranges[1][2]
 end
 
+function fuzzy.error_info(err) return { code = 'lua', message = tostring(err), context = {} } end
+
+--- @type blink.cmp.FuzzyImplementation
+--- @diagnostic disable-next-line: missing-fields
+local non_throwing = {}
+for name, func in pairs(fuzzy) do
+  if type(func) == 'function' then
+    non_throwing[name] = function(...)
+      local result = { pcall(func, ...) }
+      if not result[1] then return nil, fuzzy.error_info(result[2]) end
+      return unpack(result, 2, table.maxn(result))
+    end
+  end
+end
+fuzzy.try = non_throwing
+
 return fuzzy
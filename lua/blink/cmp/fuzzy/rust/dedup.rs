@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use mlua::prelude::*;
+
+use crate::fuzzy::FuzzyMatch;
+use crate::lsp_item::CompletionItemKind;
+
+/// Key used to decide whether two items are duplicates of each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupKey {
+    Label,
+    LabelKind,
+    InsertText,
+}
+
+/// Which item to keep when multiple items share the same key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupKeep {
+    /// Keep the item with the highest score, falling back to the provider order
+    Score,
+    /// Keep the item from the provider listed first, falling back to the score
+    Provider,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DedupOptions {
+    pub key: DedupKey,
+    pub keep: DedupKeep,
+}
+
+impl TryFrom<&String> for DedupKey {
+    type Error = mlua::Error;
+
+    fn try_from(s: &String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "label" => Ok(DedupKey::Label),
+            "label_kind" => Ok(DedupKey::LabelKind),
+            "insert_text" => Ok(DedupKey::InsertText),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "DedupKey".to_string(),
+                message: Some(format!(
+                    "Invalid dedup key: {}. Expected one of: label, label_kind, insert_text",
+                    s
+                )),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&String> for DedupKeep {
+    type Error = mlua::Error;
+
+    fn try_from(s: &String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "score" => Ok(DedupKeep::Score),
+            "provider" => Ok(DedupKeep::Provider),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "DedupKeep".to_string(),
+                message: Some(format!(
+                    "Invalid dedup keep: {}. Expected one of: score, provider",
+                    s
+                )),
+            }),
+        }
+    }
+}
+
+impl FromLua for DedupOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let Some(tab) = value.as_table() {
+            let key: String = tab.get("key").unwrap_or_else(|_| "label".to_string());
+            let keep: String = tab.get("keep").unwrap_or_else(|_| "score".to_string());
+
+            Ok(DedupOptions {
+                key: (&key).try_into()?,
+                keep: (&keep).try_into()?,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "DedupOptions".to_string(),
+                message: None,
+            })
+        }
+    }
+}
+
+/// A match which was merged into another match by deduplication
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergedMatch {
+    pub provider_idx: u16,
+    pub index: u32,
+}
+
+impl IntoLua for MergedMatch {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("provider_idx", self.provider_idx)?;
+        tab.set("index", self.index)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+impl DedupKey {
+    fn key_of(&self, mtch: &FuzzyMatch) -> (String, Option<CompletionItemKind>) {
+        match self {
+            DedupKey::Label => (mtch.item.label.clone(), None),
+            DedupKey::LabelKind => (mtch.item.label.clone(), Some(mtch.item.kind)),
+            // Snippet syntax is removed, so that `foo($1)` and `foo()` are duplicates
+            DedupKey::InsertText => (
+                mtch.item
+                    .expanded_insert_text()
+                    .unwrap_or_else(|| mtch.item.label.clone()),
+                None,
+            ),
+        }
+    }
+}
+
+impl DedupKeep {
+    /// Returns true when `a` should be kept over `b`
+    fn prefers(&self, a: &FuzzyMatch, b: &FuzzyMatch) -> bool {
+        let rank = |m: &FuzzyMatch| match self {
+            DedupKeep::Score => (-(m.score as i64), m.provider_idx as i64, m.mtch.index),
+            DedupKeep::Provider => (m.provider_idx as i64, -(m.score as i64), m.mtch.index),
+        };
+        rank(a) < rank(b)
+    }
+}
+
+/// Collapses matches sharing the same key into a single match, recording the matches which were
+/// merged away on the kept match
+pub fn dedup<'a>(matches: Vec<FuzzyMatch<'a>>, opts: &DedupOptions) -> Vec<FuzzyMatch<'a>> {
+    let mut idx_by_key: HashMap<(String, Option<CompletionItemKind>), usize> = HashMap::new();
+    let mut deduped: Vec<FuzzyMatch<'a>> = Vec::with_capacity(matches.len());
+
+    for mut mtch in matches {
+        let key = opts.key.key_of(&mtch);
+        let Some(&existing_idx) = idx_by_key.get(&key) else {
+            idx_by_key.insert(key, deduped.len());
+            deduped.push(mtch);
+            continue;
+        };
+
+        // Ensure `kept` holds the preferred match and `mtch` holds the one being merged away
+        let kept = &mut deduped[existing_idx];
+        if opts.keep.prefers(&mtch, kept) {
+            std::mem::swap(kept, &mut mtch);
+        }
+        kept.merged.push(MergedMatch {
+            provider_idx: mtch.provider_idx,
+            index: mtch.mtch.index,
+        });
+        kept.merged.append(&mut mtch.merged);
+    }
+
+    deduped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_item::LspItem;
+
+    fn item(label: &str, kind: CompletionItemKind) -> LspItem {
+        LspItem {
+            label: label.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
+
+    fn mtch(provider_idx: u16, index: u32, item: &LspItem, score: i32) -> FuzzyMatch<'_> {
+        FuzzyMatch {
+            provider_idx,
+            mtch: frizbee::Match {
+                score: score as u16,
+                index,
+                exact: false,
+            },
+            item,
+            score,
+            merged: vec![],
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let (foo_text, foo_fn, bar) = (
+            item("foo", CompletionItemKind::Text),
+            item("foo", CompletionItemKind::Function),
+            item("bar", CompletionItemKind::Text),
+        );
+        let matches = || {
+            vec![
+                mtch(0, 0, &foo_text, 10),
+                mtch(1, 0, &foo_fn, 20),
+                mtch(1, 1, &bar, 5),
+            ]
+        };
+        let merged_into_foo = |opts: DedupOptions| {
+            let deduped = dedup(matches(), &opts);
+            let foo = deduped.iter().find(|m| m.item.label == "foo").unwrap();
+            (deduped.len(), foo.provider_idx, foo.merged.clone())
+        };
+
+        assert_eq!(
+            merged_into_foo(DedupOptions {
+                key: DedupKey::Label,
+                keep: DedupKeep::Score,
+            }),
+            (
+                2,
+                1,
+                vec![MergedMatch {
+                    provider_idx: 0,
+                    index: 0
+                }]
+            )
+        );
+        assert_eq!(
+            merged_into_foo(DedupOptions {
+                key: DedupKey::Label,
+                keep: DedupKeep::Provider,
+            }),
+            (
+                2,
+                0,
+                vec![MergedMatch {
+                    provider_idx: 1,
+                    index: 0
+                }]
+            )
+        );
+        assert_eq!(
+            merged_into_foo(DedupOptions {
+                key: DedupKey::LabelKind,
+                keep: DedupKeep::Score,
+            })
+            .0,
+            3
+        );
+    }
+
+    #[test]
+    fn test_dedup_insert_text_without_snippet_syntax() {
+        let insert_text = |insert_text: &str, insert_text_format: u32| LspItem {
+            label: "foo".to_string(),
+            insert_text: Some(insert_text.to_string()),
+            insert_text_format: Some(insert_text_format),
+            ..Default::default()
+        };
+        let (snippet, plain, other) = (
+            insert_text("foo($1)", 2),
+            insert_text("foo()", 1),
+            insert_text("foo($1)", 1),
+        );
+        let deduped = dedup(
+            vec![
+                mtch(0, 0, &snippet, 10),
+                mtch(1, 0, &plain, 20),
+                mtch(1, 1, &other, 5),
+            ],
+            &DedupOptions {
+                key: DedupKey::InsertText,
+                keep: DedupKeep::Score,
+            },
+        );
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(
+            deduped[0].merged,
+            vec![MergedMatch {
+                provider_idx: 0,
+                index: 0
+            }]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::keyword::{self, KeywordClass};
+use crate::lsp_item::LspItem;
+
+/// Guesses the range on the line that the item should replace, given the keyword range
+pub fn guess_item_range(
+    item: &LspItem,
+    line: &str,
+    keyword_range: (usize, usize),
+    keyword_class: &KeywordClass,
+) -> (usize, usize) {
+    let guess_range =
+        |word: &str| keyword::guess_keyword_range(keyword_range, word, line, keyword_class);
+
+    let label_edit_range = guess_range(&item.label);
+    let filter_text_edit_range = item
+        .filter_text
+        .as_ref()
+        .map(|filter_text| guess_range(filter_text))
+        .unwrap_or(label_edit_range);
+    let insert_text_edit_range = item
+        .plain_insert_text()
+        .map(guess_range)
+        .unwrap_or(filter_text_edit_range);
+
+    // Prefer the insert text, then filter text, then label ranges for non-snippets
+    if !item.is_snippet() {
+        return insert_text_edit_range;
+    }
+
+    // HACK: In the lazydev.nvim case, the label is the whole module like `blink.cmp.fuzzy`
+    // but the `insertText` is just `fuzzy` when you've already typed `blink.cmp.`.
+    // But in the snippets case, the label could be completed unrelated to the insertText so we
+    // should use the label range.
+    //
+    // TODO: What about using the filterText range and ignoring label?
+
+    // Take the max range prioritizing the start index first and the end index second
+    // When comparing tuples (start, end), Rust compares the first element first,
+    // and only if those are equal, it compares the second element
+    [
+        label_edit_range,
+        filter_text_edit_range,
+        insert_text_edit_range,
+    ]
+    .iter()
+    // Transform to (start, -end) to find minimum start and maximum end
+    .min_by_key(|&(start, end)| (start, std::cmp::Reverse(end)))
+    .copied()
+    .unwrap_or((0, 0))
+}
+
+/// Characters which continue the line when found at the end of it, i.e. `str/\n  tr`
+const TRAILING_CONTINUATIONS: &[char] = &[
+    '\\', '.', '+', '-', '*', '/', '%', '=', '&', '|', '^', '<', '!', '~', '?',
+];
+/// Continue the line at the end of it, in addition to `TRAILING_CONTINUATIONS`. `:` and `>` alone
+/// aren't included since they typically end a statement, i.e. `def foo():` or `<div>`
+const TRAILING_CONTINUATION_OPERATORS: &[&str] = &["::", "->", "=>"];
+/// Continue the previous line at the start of the line, i.e. method chains (`foo\n  .bar`)
+const LEADING_CONTINUATIONS: &[&str] = &[".", "?.", "->", "::"];
+
+/// Whether the line continues onto the next line, only considering explicit continuations
+/// (trailing `\`, `.` or an operator, or a leading `.`), so that `foo;\nbar` isn't joined
+fn is_continuation(prev_line: &str, next_text: &str) -> bool {
+    prev_line.ends_with(TRAILING_CONTINUATIONS)
+        || TRAILING_CONTINUATION_OPERATORS
+            .iter()
+            .any(|op| prev_line.ends_with(op))
+        || LEADING_CONTINUATIONS
+            .iter()
+            .any(|op| next_text.starts_with(op))
+}
+
+/// The cursor line joined with the preceding lines it continues, such as method chains broken
+/// across lines (`foo\n  .bar`) or lines ending in an operator (`str/\n  tr`). Leading and
+/// trailing whitespace around each line break is removed, so that the keyword guessing can treat
+/// the joined text as a single line
+#[derive(Debug)]
+pub struct JoinedLine {
+    pub text: String,
+    pub cursor_col: usize,
+    /// (start offset in text, row, col in row), sorted by the start offset
+    segments: Vec<(usize, usize, usize)>,
+}
+
+impl JoinedLine {
+    pub fn new(lines: &[&str], cursor_row: usize, cursor_col: usize) -> Self {
+        // Walk backwards, collecting the (row, indent, text without indent) of each line
+        let cursor_line = lines[cursor_row];
+        let indent = cursor_line.len() - cursor_line.trim_start().len();
+        let mut parts = vec![];
+        if cursor_col < indent {
+            parts.push((cursor_row, 0, cursor_line));
+        } else {
+            parts.push((cursor_row, indent, &cursor_line[indent..]));
+            for row in (0..cursor_row).rev() {
+                let next_text = parts[parts.len() - 1].2;
+                let prev_line = lines[row].trim_end();
+                let prev_indent = prev_line.len() - prev_line.trim_start().len();
+                if prev_line.trim_start().is_empty() || !is_continuation(prev_line, next_text) {
+                    break;
+                }
+                parts.push((row, prev_indent, &prev_line[prev_indent..]));
+            }
+        }
+        parts.reverse();
+
+        let mut text = String::new();
+        let mut segments = vec![];
+        let mut joined_cursor_col = cursor_col;
+        for (row, col, part) in parts {
+            segments.push((text.len(), row, col));
+            if row == cursor_row {
+                joined_cursor_col = text.len() + cursor_col - col;
+            }
+            text.push_str(part);
+        }
+
+        JoinedLine {
+            text,
+            cursor_col: joined_cursor_col,
+            segments,
+        }
+    }
+
+    /// Converts an offset in the joined text to a (row, col) position
+    pub fn to_position(&self, offset: usize) -> (usize, usize) {
+        let &(start, row, col) = self
+            .segments
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= offset)
+            .unwrap_or(&self.segments[0]);
+        (row, col + offset - start)
+    }
+}
+
+/// Guesses the range that the item should replace, potentially spanning multiple lines, given
+/// the lines surrounding the cursor. Returns (start_row, start_col, end_row, end_col) where the
+/// rows are indices into `lines`
+pub fn guess_multiline_item_range(
+    item: &LspItem,
+    lines: &[&str],
+    cursor_row: usize,
+    cursor_col: usize,
+    match_suffix: bool,
+    keyword_class: &KeywordClass,
+) -> (usize, usize, usize, usize) {
+    let joined = JoinedLine::new(lines, cursor_row, cursor_col);
+    let keyword_range =
+        keyword::get_keyword_range(&joined.text, joined.cursor_col, match_suffix, keyword_class);
+    let (start, end) = guess_item_range(item, &joined.text, keyword_range, keyword_class);
+
+    let (start_row, start_col) = joined.to_position(start);
+    let (end_row, end_col) = joined.to_position(end);
+    (start_row, start_col, end_row, end_col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_item::CompletionItemKind;
+
+    fn item(label: &str) -> LspItem {
+        LspItem {
+            label: label.to_string(),
+            kind: CompletionItemKind::Method,
+            ..Default::default()
+        }
+    }
+
+    fn guess(lines: &[&str], label: &str) -> (usize, usize, usize, usize) {
+        let cursor_row = lines.len() - 1;
+        let cursor_col = lines[cursor_row].len();
+        guess_multiline_item_range(
+            &item(label),
+            lines,
+            cursor_row,
+            cursor_col,
+            false,
+            &KeywordClass::default(),
+        )
+    }
+
+    #[test]
+    fn test_guess_item_range_snippet() {
+        let mut item = item("log");
+        item.kind = CompletionItemKind::Snippet;
+        item.insert_text = Some("${1:console}.log($0)".to_string());
+        item.insert_text_format = Some(2);
+        item.snippet_prefix = Some("console.log(".to_string());
+        let line = "  console.lo";
+        let keyword_range = keyword::get_keyword_range(line, 12, false, &KeywordClass::default());
+        assert_eq!(
+            guess_item_range(&item, line, keyword_range, &KeywordClass::default()),
+            (2, 12)
+        );
+    }
+
+    #[test]
+    fn test_guess_multiline_item_range_single_line() {
+        assert_eq!(guess(&["foo.ba"], "bar"), (0, 4, 0, 6));
+        assert_eq!(guess(&["  str/tr"], "str/trim"), (0, 2, 0, 8));
+    }
+
+    #[test]
+    fn test_guess_multiline_item_range_chained_call() {
+        let lines = ["let x = foo", "  .bar()", "  .ba"];
+        assert_eq!(guess(&lines, "baz"), (2, 3, 2, 5));
+        assert_eq!(guess(&lines, ".baz"), (2, 2, 2, 5));
+        assert_eq!(guess(&lines, "bar().baz"), (1, 3, 2, 5));
+
+        let lines = ["foo", "  .ba"];
+        assert_eq!(guess(&lines, "foo.bar"), (0, 0, 1, 5));
+    }
+
+    #[test]
+    fn test_guess_multiline_item_range_continuation() {
+        // Trailing operator on the previous line
+        let lines = ["x = str/", "    tr"];
+        assert_eq!(guess(&lines, "str/trim"), (0, 4, 1, 6));
+
+        // Lines without a continuation aren't joined
+        let lines = ["foo", "bar"];
+        assert_eq!(guess(&lines, "foobar"), (1, 0, 1, 3));
+        let lines = ["", "  .ba"];
+        assert_eq!(guess(&lines, "x.bar"), (1, 3, 1, 5));
+
+        // Trailing backslash
+        let lines = ["foo \\", "  ba"];
+        assert_eq!(guess(&lines, "\\bar"), (0, 4, 1, 4));
+    }
+
+    #[test]
+    fn test_guess_multiline_item_range_no_continuation() {
+        // Punctuation ending a statement or opening a block isn't a continuation
+        for prev_line in ["foo;", "fn foo() {", "foo(a,", "def foo():", "<div>", "}"] {
+            let lines = [prev_line, "  ba"];
+            assert_eq!(
+                guess(&lines, &format!("{prev_line}bar")),
+                (1, 2, 1, 4),
+                "{prev_line:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use mlua::prelude::*;
+
+/// Unit used for columns in a line, matching LSP's `PositionEncodingKind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    /// Bytes, as used by Neovim
+    Utf8,
+    /// UTF-16 code units, the default for LSP
+    #[default]
+    Utf16,
+    /// Unicode codepoints
+    Utf32,
+}
+
+impl PositionEncoding {
+    fn len(&self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+impl TryFrom<&str> for PositionEncoding {
+    type Error = mlua::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "utf-8" => Ok(PositionEncoding::Utf8),
+            "utf-16" => Ok(PositionEncoding::Utf16),
+            "utf-32" => Ok(PositionEncoding::Utf32),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "PositionEncoding".to_string(),
+                message: Some(format!(
+                    "Invalid position encoding: {}. Expected one of: utf-8, utf-16, utf-32",
+                    s
+                )),
+            }),
+        }
+    }
+}
+
+impl FromLua for PositionEncoding {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        String::from_lua(value, lua)?.as_str().try_into()
+    }
+}
+
+/// Converts a column in the line from one encoding to another. Columns pointing inside of a
+/// character resolve to the start of the character and columns past the end of the line resolve
+/// to the end of the line
+pub fn convert_col(line: &str, col: usize, from: PositionEncoding, to: PositionEncoding) -> usize {
+    let mut from_col = 0;
+    let mut to_col = 0;
+    for c in line.chars() {
+        from_col += from.len(c);
+        if from_col > col {
+            return to_col;
+        }
+        to_col += to.len(c);
+    }
+    to_col
+}
+
+/// Converts a column in the given encoding to a byte offset in the line
+pub fn to_byte_col(line: &str, col: usize, encoding: PositionEncoding) -> usize {
+    convert_col(line, col, encoding, PositionEncoding::Utf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PositionEncoding::*;
+    use super::*;
+
+    #[test]
+    fn test_convert_col_ascii() {
+        let line = "hello world";
+        for encoding in [Utf8, Utf16, Utf32] {
+            for col in 0..=line.len() {
+                assert_eq!(to_byte_col(line, col, encoding), col);
+                assert_eq!(convert_col(line, col, Utf8, encoding), col);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_col_multibyte() {
+        // "é" is 2 bytes and 1 UTF-16 code unit, "😀" is 4 bytes and 2 UTF-16 code units
+        let line = "aé😀b";
+        let byte_cols = [0, 1, 3, 7, 8];
+        let utf16_cols = [0, 1, 2, 4, 5];
+        let utf32_cols = [0, 1, 2, 3, 4];
+        for i in 0..byte_cols.len() {
+            assert_eq!(to_byte_col(line, utf16_cols[i], Utf16), byte_cols[i]);
+            assert_eq!(to_byte_col(line, utf32_cols[i], Utf32), byte_cols[i]);
+            assert_eq!(convert_col(line, byte_cols[i], Utf8, Utf16), utf16_cols[i]);
+            assert_eq!(convert_col(line, byte_cols[i], Utf8, Utf32), utf32_cols[i]);
+            assert_eq!(
+                convert_col(line, utf16_cols[i], Utf16, Utf32),
+                utf32_cols[i]
+            );
+            assert_eq!(
+                convert_col(line, utf32_cols[i], Utf32, Utf16),
+                utf16_cols[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert_col_inside_char() {
+        let line = "a😀b";
+        // Inside the surrogate pair
+        assert_eq!(to_byte_col(line, 2, Utf16), 1);
+        // Inside the UTF-8 sequence
+        assert_eq!(convert_col(line, 3, Utf8, Utf16), 1);
+        assert_eq!(convert_col(line, 3, Utf8, Utf8), 1);
+    }
+
+    #[test]
+    fn test_convert_col_past_end() {
+        let line = "aé";
+        assert_eq!(to_byte_col(line, 10, Utf16), 3);
+        assert_eq!(convert_col(line, 10, Utf8, Utf16), 2);
+        assert_eq!(to_byte_col("", 5, Utf32), 0);
+    }
+}
@@ -1,30 +1,330 @@
+use mlua::prelude::*;
+
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
-    #[error("Failed to acquire lock for frecency")]
-    AcquireFrecencyLock,
-
-    #[error("Failed to acquire lock for items by provider")]
-    AcquireItemLock,
-
     #[error("Attempted to use frecency before initialization")]
     UseFrecencyBeforeInit,
 
     #[error(
-        "Attempted to fuzzy match for provider {provider_id} before setting the provider's items"
+        "Attempted to fuzzy match for provider {provider_id} in namespace {namespace} before setting the provider's items"
+    )]
+    FuzzyBeforeSetItems {
+        namespace: String,
+        provider_id: String,
+    },
+
+    #[error(
+        "Attempted to update the items of provider {provider_id} in namespace {namespace} before setting them"
     )]
-    FuzzyBeforeSetItems { provider_id: String },
+    UpdateBeforeSetItems {
+        namespace: String,
+        provider_id: String,
+    },
+
+    #[error("Item index {index} is out of bounds for provider {provider_id} with {len} items")]
+    ItemIndexOutOfBounds {
+        provider_id: String,
+        index: usize,
+        len: usize,
+    },
+
+    #[error("Attempted to update the words of buffer {bufnr} before setting the buffer's lines")]
+    UpdateBeforeSetBufferLines { bufnr: u32 },
+
+    #[error("Invalid regex {pattern}: {err}")]
+    InvalidRegex { pattern: String, err: regex::Error },
+
+    #[error("Invalid part {part:?} in iskeyword {iskeyword:?}")]
+    InvalidIsKeyword { iskeyword: String, part: String },
+
+    #[error("Cursor row {cursor_row} is out of bounds for {line_count} lines")]
+    CursorOutOfBounds {
+        cursor_row: usize,
+        line_count: usize,
+    },
+
+    #[error("Panicked: {message}")]
+    Panic { message: String },
+
+    #[error("Async fuzzy matching stopped before returning a result")]
+    FuzzyTaskStopped,
+
+    #[error("Invalid packed items: {reason}")]
+    InvalidPackedItems { reason: String },
+
+    #[error("Invalid matcher options: {reason}")]
+    InvalidMatcherOptions { reason: String },
+
+    #[error("Failed to decode packed items: {0}")]
+    MsgpackDecodeError(#[from] rmpv::decode::Error),
 
     #[error("Failed to create frecency database directory: {0}")]
     IoError(#[from] std::io::Error),
 
+    #[error("Failed to access frecency database {path}: {err}")]
+    FrecencyIoError {
+        path: std::path::PathBuf,
+        err: std::io::Error,
+    },
+
     #[error("Failed to decode frecency entry: {0}")]
     BincodeDecodeError(#[from] bincode::error::DecodeError),
     #[error("Failed to encode frecency entry: {0}")]
     BincodeEncodeError(#[from] bincode::error::EncodeError),
 }
 
+impl Error {
+    /// Stable identifier of the error, for matching on in Lua. Codes of removed errors are
+    /// reserved and never reused: `acquire_frecency_lock`, `acquire_item_lock`,
+    /// `acquire_word_index_lock`, `acquire_regex_cache_lock` and `acquire_keyword_class_lock`,
+    /// which are no longer raised since poisoned locks are recovered (see `recovery`)
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UseFrecencyBeforeInit => "use_frecency_before_init",
+            Error::FuzzyBeforeSetItems { .. } => "fuzzy_before_set_items",
+            Error::UpdateBeforeSetItems { .. } => "update_before_set_items",
+            Error::ItemIndexOutOfBounds { .. } => "item_index_out_of_bounds",
+            Error::UpdateBeforeSetBufferLines { .. } => "update_before_set_buffer_lines",
+            Error::InvalidRegex { .. } => "invalid_regex",
+            Error::InvalidIsKeyword { .. } => "invalid_iskeyword",
+            Error::CursorOutOfBounds { .. } => "cursor_out_of_bounds",
+            Error::Panic { .. } => "panic",
+            Error::FuzzyTaskStopped => "fuzzy_task_stopped",
+            Error::InvalidPackedItems { .. } => "invalid_packed_items",
+            Error::InvalidMatcherOptions { .. } => "invalid_matcher_options",
+            Error::MsgpackDecodeError(_) => "msgpack_decode",
+            Error::IoError(_) => "io",
+            Error::FrecencyIoError { .. } => "frecency_io",
+            Error::BincodeDecodeError(_) => "bincode_decode",
+            Error::BincodeEncodeError(_) => "bincode_encode",
+        }
+    }
+
+    /// Attaches the path of the frecency database to I/O errors
+    pub fn with_frecency_path(self, path: &std::path::Path) -> Self {
+        match self {
+            Error::IoError(err) => Error::FrecencyIoError {
+                path: path.to_path_buf(),
+                err,
+            },
+            err => err,
+        }
+    }
+
+    /// Fields describing what the error applies to, such as the provider id
+    fn context(&self) -> Vec<(&'static str, ContextValue)> {
+        match self {
+            Error::FuzzyBeforeSetItems {
+                namespace,
+                provider_id,
+            }
+            | Error::UpdateBeforeSetItems {
+                namespace,
+                provider_id,
+            } => vec![
+                ("namespace", namespace.as_str().into()),
+                ("provider_id", provider_id.as_str().into()),
+            ],
+            Error::ItemIndexOutOfBounds {
+                provider_id,
+                index,
+                len,
+            } => vec![
+                ("provider_id", provider_id.as_str().into()),
+                ("index", (*index).into()),
+                ("len", (*len).into()),
+            ],
+            Error::UpdateBeforeSetBufferLines { bufnr } => {
+                vec![("bufnr", (*bufnr as usize).into())]
+            }
+            Error::InvalidRegex { pattern, .. } => vec![("pattern", pattern.as_str().into())],
+            Error::InvalidIsKeyword { iskeyword, part } => vec![
+                ("iskeyword", iskeyword.as_str().into()),
+                ("part", part.as_str().into()),
+            ],
+            Error::CursorOutOfBounds {
+                cursor_row,
+                line_count,
+            } => vec![
+                ("cursor_row", (*cursor_row).into()),
+                ("line_count", (*line_count).into()),
+            ],
+            Error::Panic { message } => vec![("panic", message.as_str().into())],
+            Error::FrecencyIoError { path, .. } => {
+                vec![("path", path.to_string_lossy().as_ref().into())]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextValue {
+    String(String),
+    Number(usize),
+}
+
+impl From<&str> for ContextValue {
+    fn from(value: &str) -> Self {
+        ContextValue::String(value.to_string())
+    }
+}
+
+impl From<usize> for ContextValue {
+    fn from(value: usize) -> Self {
+        ContextValue::Number(value)
+    }
+}
+
+impl IntoLua for ContextValue {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        match self {
+            ContextValue::String(value) => value.into_lua(lua),
+            ContextValue::Number(value) => value.into_lua(lua),
+        }
+    }
+}
+
+/// An error with a stable `code`, the `message` and the `context`. Errors from outside of this
+/// module, such as invalid arguments, use the `lua` code
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub message: String,
+    pub context: Vec<(&'static str, ContextValue)>,
+}
+
+impl From<&mlua::Error> for ErrorInfo {
+    fn from(err: &mlua::Error) -> Self {
+        match err.chain().find_map(|err| err.downcast_ref::<Error>()) {
+            Some(err) => ErrorInfo {
+                code: err.code(),
+                message: err.to_string(),
+                context: err.context(),
+            },
+            None => ErrorInfo {
+                code: "lua",
+                // Skip the wrapping callback errors, which only add a traceback
+                message: err
+                    .chain()
+                    .last()
+                    .map_or_else(|| err.to_string(), |err| err.to_string()),
+                context: vec![],
+            },
+        }
+    }
+}
+
+impl IntoLua for ErrorInfo {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let info = lua.create_table()?;
+        info.set("code", self.code)?;
+        info.set("message", self.message)?;
+        let context = lua.create_table()?;
+        for (key, value) in self.context {
+            context.set(key, value)?;
+        }
+        info.set("context", context)?;
+        Ok(LuaValue::Table(info))
+    }
+}
+
+/// Result of a function wrapped to return `nil, err` instead of raising, see `ErrorInfo`
+#[derive(Debug)]
+pub enum NonThrowing<T> {
+    Ok(T),
+    Err(ErrorInfo),
+}
+
+impl<T> From<LuaResult<T>> for NonThrowing<T> {
+    fn from(result: LuaResult<T>) -> Self {
+        match result {
+            Ok(values) => NonThrowing::Ok(values),
+            Err(err) => NonThrowing::Err(ErrorInfo::from(&err)),
+        }
+    }
+}
+
+impl<T: IntoLuaMulti> IntoLuaMulti for NonThrowing<T> {
+    fn into_lua_multi(self, lua: &Lua) -> LuaResult<LuaMultiValue> {
+        match self {
+            NonThrowing::Ok(values) => values.into_lua_multi(lua),
+            NonThrowing::Err(info) => (LuaNil, info).into_lua_multi(lua),
+        }
+    }
+}
+
 impl From<Error> for mlua::Error {
     fn from(value: Error) -> Self {
-        mlua::Error::RuntimeError(value.to_string())
+        mlua::Error::external(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frecency_path() {
+        let err = Error::from(std::io::Error::other("disk full"));
+        assert_eq!(err.code(), "io");
+
+        let err = err.with_frecency_path(std::path::Path::new("/tmp/frecency.dat"));
+        assert_eq!(err.code(), "frecency_io");
+        assert_eq!(
+            err.to_string(),
+            "Failed to access frecency database /tmp/frecency.dat: disk full"
+        );
+
+        let err = mlua::Error::from(err);
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::FrecencyIoError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_error_info() {
+        let err = mlua::Error::from(Error::FuzzyBeforeSetItems {
+            namespace: "default".to_string(),
+            provider_id: "lsp".to_string(),
+        });
+        // Errors raised from functions are wrapped in a callback error with the traceback
+        #[allow(clippy::arc_with_non_send_sync)]
+        let err = mlua::Error::CallbackError {
+            traceback: "stack traceback:".to_string(),
+            cause: std::sync::Arc::new(err),
+        };
+        let info = ErrorInfo::from(&err);
+        assert_eq!(info.code, "fuzzy_before_set_items");
+        assert_eq!(
+            info.context,
+            vec![
+                ("namespace", "default".into()),
+                ("provider_id", "lsp".into())
+            ]
+        );
+
+        let info = ErrorInfo::from(&mlua::Error::runtime("bad argument"));
+        assert_eq!(info.code, "lua");
+        assert_eq!(info.message, "runtime error: bad argument");
+        assert!(info.context.is_empty());
+    }
+
+    #[test]
+    fn test_non_throwing() {
+        assert!(matches!(NonThrowing::from(Ok(1)), NonThrowing::Ok(1)));
+
+        let err = mlua::Error::from(Error::ItemIndexOutOfBounds {
+            provider_id: "lsp".to_string(),
+            index: 3,
+            len: 2,
+        });
+        let NonThrowing::<()>::Err(info) = NonThrowing::from(Err(err)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(info.code, "item_index_out_of_bounds");
+        assert!(info.context.contains(&("index", 3.into())));
     }
 }
@@ -0,0 +1,93 @@
+use mlua::prelude::*;
+use regex::Regex;
+
+use crate::lsp_item::{CompletionItemKind, LspItem};
+use crate::regex_cache::get_regex;
+
+/// Declarative filters applied while matching, so that excluded items are never scored, sorted
+/// or returned to Lua
+#[derive(Debug, Clone, Default)]
+pub struct FilterOptions {
+    pub exclude_kinds: Vec<CompletionItemKind>,
+    pub exclude_deprecated: bool,
+    /// Minimum score, including all bonuses and offsets, for a match to be kept
+    pub min_score: Option<i32>,
+    /// Excludes items whose label matches the regex
+    pub exclude_label: Option<Regex>,
+}
+
+impl FromLua for FilterOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let Some(tab) = value.as_table() {
+            let exclude_kinds: Vec<CompletionItemKind> =
+                tab.get::<Option<_>>("exclude_kinds")?.unwrap_or_default();
+            let exclude_deprecated: bool = tab.get("exclude_deprecated").unwrap_or_default();
+            let min_score: Option<i32> = tab.get("min_score")?;
+            let exclude_label = tab
+                .get::<Option<String>>("exclude_label")?
+                .map(|pattern| get_regex(&pattern))
+                .transpose()?;
+
+            Ok(FilterOptions {
+                exclude_kinds,
+                exclude_deprecated,
+                min_score,
+                exclude_label,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "FilterOptions".to_string(),
+                message: None,
+            })
+        }
+    }
+}
+
+impl FilterOptions {
+    /// Whether the item should be skipped before matching
+    pub fn excludes(&self, item: &LspItem) -> bool {
+        (self.exclude_deprecated && item.deprecated)
+            || self.exclude_kinds.contains(&item.kind)
+            || self
+                .exclude_label
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(&item.label))
+    }
+
+    /// Whether a match with the given score should be kept
+    pub fn keeps_score(&self, score: i32) -> bool {
+        self.min_score.is_none_or(|min_score| score >= min_score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excludes() {
+        let item = |label: &str, kind: CompletionItemKind, deprecated: bool| LspItem {
+            label: label.to_string(),
+            kind,
+            deprecated,
+            ..Default::default()
+        };
+        let filter = FilterOptions {
+            exclude_kinds: vec![CompletionItemKind::Text],
+            exclude_deprecated: true,
+            min_score: Some(5),
+            exclude_label: Some(get_regex("^_").unwrap()),
+        };
+
+        assert!(!filter.excludes(&item("foo", CompletionItemKind::Function, false)));
+        assert!(filter.excludes(&item("foo", CompletionItemKind::Text, false)));
+        assert!(filter.excludes(&item("foo", CompletionItemKind::Function, true)));
+        assert!(filter.excludes(&item("_foo", CompletionItemKind::Function, false)));
+        assert!(!FilterOptions::default().excludes(&item("_foo", CompletionItemKind::Text, true)));
+
+        assert!(filter.keeps_score(5));
+        assert!(!filter.keeps_score(4));
+        assert!(FilterOptions::default().keeps_score(i32::MIN));
+    }
+}
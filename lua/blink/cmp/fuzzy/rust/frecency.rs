@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use bincode::{Decode, Encode};
@@ -30,6 +30,17 @@ struct FrecencyEntry {
 pub struct FrecencyDB {
     path: PathBuf,
     cache: HashMap<[u8; 32], (u64, FrecencyEntry)>, // hash -> (file_position, entry)
+    /// Number of entries skipped on the last reload since they failed to decode
+    decode_errors: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrecencyStats {
+    pub path: PathBuf,
+    pub entries: usize,
+    pub file_size: u64,
+    /// Whether every entry decoded and the file only contains whole entries
+    pub decoded_cleanly: bool,
 }
 
 impl FrecencyDB {
@@ -46,6 +57,7 @@ impl FrecencyDB {
         let mut db = FrecencyDB {
             path: path.clone(),
             cache: HashMap::new(),
+            decode_errors: 0,
         };
 
         // Load cache on initialization
@@ -54,6 +66,20 @@ impl FrecencyDB {
         Ok(db)
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn stats(&self) -> Result<FrecencyStats, Error> {
+        let file_size = std::fs::metadata(&self.path)?.len();
+        Ok(FrecencyStats {
+            path: self.path.clone(),
+            entries: self.cache.len(),
+            file_size,
+            decoded_cleanly: self.decode_errors == 0 && file_size % ENTRY_SIZE as u64 == 0,
+        })
+    }
+
     /// Gets the score for a given item
     pub fn get_score(&self, key: &Hash) -> Option<f64> {
         self.get(key).map(|(timestamp, score)| {
@@ -66,9 +92,14 @@ impl FrecencyDB {
         })
     }
 
-    /// Accesses a given item
-    pub fn access(&mut self, key: &Hash) -> Result<(), Error> {
-        let score = self.get_score(&key).unwrap_or(0.0);
+    /// Gets the score for a given item, falling back to the key from the previous hash version
+    pub fn get_versioned_score(&self, key: &Hash, legacy_key: &Hash) -> Option<f64> {
+        self.get_score(key).or_else(|| self.get_score(legacy_key))
+    }
+
+    /// Accesses a given item, carrying over the score from the previous hash version
+    pub fn access(&mut self, key: &Hash, legacy_key: &Hash) -> Result<(), Error> {
+        let score = self.get_versioned_score(key, legacy_key).unwrap_or(0.0);
 
         let current_timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -130,10 +161,11 @@ impl FrecencyDB {
         Ok(())
     }
 
-    fn reload_cache(&mut self) -> Result<(), Error> {
+    pub fn reload_cache(&mut self) -> Result<(), Error> {
         let mut file = OpenOptions::new().read(true).open(&self.path)?;
 
         let mut new_cache = HashMap::new();
+        let mut decode_errors = 0;
         let mut buffer = [0u8; ENTRY_SIZE];
         let mut position = 0u64;
 
@@ -141,9 +173,16 @@ impl FrecencyDB {
             file.seek(SeekFrom::Start(position))?;
             match file.read_exact(&mut buffer) {
                 Ok(_) => {
-                    let entry: FrecencyEntry =
-                        bincode::decode_from_slice(&buffer, bincode::config::standard())?.0;
-                    new_cache.insert(entry.hash, (position, entry));
+                    // Skip corrupted entries rather than losing the whole database
+                    match bincode::decode_from_slice::<FrecencyEntry, _>(
+                        &buffer,
+                        bincode::config::standard(),
+                    ) {
+                        Ok((entry, _)) => {
+                            new_cache.insert(entry.hash, (position, entry));
+                        }
+                        Err(_) => decode_errors += 1,
+                    }
                     position += ENTRY_SIZE as u64;
                 }
                 Err(_) => break,
@@ -151,7 +190,48 @@ impl FrecencyDB {
         }
 
         self.cache = new_cache;
+        self.decode_errors = decode_errors;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_skips_corrupted_entries() {
+        let path = std::env::temp_dir().join(format!(
+            "blink-cmp-frecency-test-{}.dat",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let key = blake3::hash(b"foo");
+        let other_key = blake3::hash(b"bar");
+
+        let mut db = FrecencyDB::new(&path).unwrap();
+        db.access(&key, &key).unwrap();
+
+        // An entry whose timestamp has an invalid varint tag, which fails to decode
+        let mut corrupted = [0u8; ENTRY_SIZE];
+        corrupted[32] = 0xff;
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&corrupted).unwrap();
+
+        let mut db = FrecencyDB::new(&path).unwrap();
+        assert!(db.get_score(&key).is_some());
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.entries, 1);
+        assert!(!stats.decoded_cleanly);
+
+        // Entries after the corrupted one are still read
+        db.access(&other_key, &other_key).unwrap();
+        let db = FrecencyDB::new(&path).unwrap();
+        assert!(db.get_score(&key).is_some());
+        assert!(db.get_score(&other_key).is_some());
+        assert_eq!(db.stats().unwrap().entries, 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
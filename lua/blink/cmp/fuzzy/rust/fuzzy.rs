@@ -1,7 +1,11 @@
 // TODO: refactor this heresy
 
+use crate::dedup::{DedupOptions, MergedMatch};
+use crate::edit_range;
+use crate::encoding::PositionEncoding;
+use crate::filter::FilterOptions;
 use crate::frecency::FrecencyDB;
-use crate::keyword;
+use crate::keyword::{self, KeywordClass};
 use crate::lsp_item::LspItem;
 use crate::sort::Sort;
 use frizbee::Match;
@@ -9,17 +13,31 @@ use mlua::prelude::*;
 use mlua::FromLua;
 use mlua::Lua;
 use std::collections::HashMap;
-use std::collections::HashSet;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FuzzyOptions {
     pub match_suffix: bool,
     pub max_typos: u16,
     pub use_frecency: bool,
     pub use_proximity: bool,
-    pub nearby_words: Option<Vec<String>>,
+    /// Words near the cursor and, when known, their distance in lines from the cursor
+    pub nearby_words: Option<HashMap<String, Option<u32>>>,
+    /// Caller computed bonuses by filter text (falling back to the label), i.e. from expected
+    /// types or recently inserted items
+    pub boosts: Option<HashMap<String, i32>>,
+    /// Maximum combined bonus from proximity and boosts
+    pub max_boost: Option<i32>,
     pub snippet_score_offset: i32,
+    /// Added to the score of deprecated items, typically negative to demote them
+    pub deprecated_score_offset: i32,
     pub sorts: Option<Vec<Sort>>,
+    pub keyword_class: KeywordClass,
+    /// Encoding of the columns in the items' `textEdit` ranges by LSP client id
+    pub offset_encodings: HashMap<u32, PositionEncoding>,
+    pub dedup: Option<DedupOptions>,
+    pub filter: Option<FilterOptions>,
+    /// Namespace of the provider items to match against, defaulting to `default`
+    pub namespace: Option<String>,
 }
 
 #[derive(Clone)]
@@ -28,17 +46,26 @@ pub struct FuzzyMatch<'a> {
     pub mtch: Match,
     pub item: &'a LspItem,
     pub score: i32,
+    /// Matches which were merged into this match when deduplicating
+    pub merged: Vec<MergedMatch>,
 }
 
 impl FromLua for FuzzyOptions {
-    fn from_lua(value: LuaValue, _lua: &'_ Lua) -> LuaResult<Self> {
+    fn from_lua(value: LuaValue, lua: &'_ Lua) -> LuaResult<Self> {
         if let Some(tab) = value.as_table() {
             let match_suffix: bool = tab.get("match_suffix").unwrap_or_default();
             let max_typos: u16 = tab.get("max_typos").unwrap_or_default();
             let use_frecency: bool = tab.get("use_frecency").unwrap_or_default();
             let use_proximity: bool = tab.get("use_proximity").unwrap_or_default();
-            let nearby_words: Option<Vec<String>> = tab.get("nearby_words").ok();
+            let nearby_words = tab
+                .get::<Option<LuaTable>>("nearby_words")?
+                .map(|tab| parse_nearby_words(lua, tab))
+                .transpose()?;
+            let boosts: Option<HashMap<String, i32>> = tab.get("boosts")?;
+            let max_boost: Option<i32> = tab.get("max_boost")?;
             let snippet_score_offset: i32 = tab.get("snippet_score_offset").unwrap_or_default();
+            let deprecated_score_offset: i32 =
+                tab.get("deprecated_score_offset").unwrap_or_default();
             let sorts: Option<Vec<String>> = tab.get("sorts").ok();
             let sorts = sorts
                 .map(|sorts| {
@@ -48,6 +75,15 @@ impl FromLua for FuzzyOptions {
                         .collect::<Result<Vec<_>, _>>()
                 })
                 .transpose()?;
+            let keyword_class: KeywordClass = tab
+                .get::<Option<KeywordClass>>("keyword")?
+                .unwrap_or_default();
+            let offset_encodings: HashMap<u32, PositionEncoding> = tab
+                .get::<Option<_>>("offset_encodings")?
+                .unwrap_or_default();
+            let dedup: Option<DedupOptions> = tab.get("dedup")?;
+            let filter: Option<FilterOptions> = tab.get("filter")?;
+            let namespace: Option<String> = tab.get("namespace")?;
 
             Ok(FuzzyOptions {
                 match_suffix,
@@ -55,8 +91,16 @@ impl FromLua for FuzzyOptions {
                 use_frecency,
                 use_proximity,
                 nearby_words,
+                boosts,
+                max_boost,
                 snippet_score_offset,
+                deprecated_score_offset,
                 sorts,
+                keyword_class,
+                offset_encodings,
+                dedup,
+                filter,
+                namespace,
             })
         } else {
             Err(mlua::Error::FromLuaConversionError {
@@ -68,16 +112,77 @@ impl FromLua for FuzzyOptions {
     }
 }
 
+/// Accepts either a list of words or a map of word -> line distance from the cursor
+fn parse_nearby_words(lua: &Lua, tab: LuaTable) -> LuaResult<HashMap<String, Option<u32>>> {
+    let words = tab
+        .pairs::<LuaValue, LuaValue>()
+        .map(|pair| match pair? {
+            (LuaValue::Integer(_), LuaValue::String(word)) => Ok((word.to_string_lossy(), None)),
+            (LuaValue::String(word), distance) => {
+                Ok((word.to_string_lossy(), Some(u32::from_lua(distance, lua)?)))
+            }
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "table",
+                to: "nearby_words".to_string(),
+                message: Some(
+                    "Expected a list of words or a map of word to line distance".to_string(),
+                ),
+            }),
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+    Ok(merge_nearby_words(words))
+}
+
+/// Collects the nearby words, keeping the closest known distance when a word appears more than
+/// once, i.e. in both the list and the map part of a mixed table
+fn merge_nearby_words(
+    words: impl IntoIterator<Item = (String, Option<u32>)>,
+) -> HashMap<String, Option<u32>> {
+    let mut nearby_words: HashMap<String, Option<u32>> = HashMap::new();
+    for (word, distance) in words {
+        let entry = nearby_words.entry(word).or_insert(distance);
+        *entry = match (*entry, distance) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+    nearby_words
+}
+
+/// Bonus for an item matching a nearby word, decreasing as the word gets further from the cursor.
+/// Words without a known distance receive a flat bonus
+fn proximity_score(distance: Option<u32>) -> i32 {
+    match distance {
+        None => 2,
+        Some(0..=1) => 3,
+        Some(2..=10) => 2,
+        Some(_) => 1,
+    }
+}
+
+/// Groups the haystack by the needle each item should be matched against. Items with a known
+/// start column (i.e. provided by the server via `textEdit`) use the text from that column to the
+/// end of the keyword, while all others guess the needle based on the keyword. Excluded items are
+/// skipped entirely
 fn group_by_needle(
     line: &str,
     cursor_col: usize,
     haystack: &[String],
+    needle_starts: &[Option<usize>],
+    excluded: &[bool],
     match_suffix: bool,
+    keyword_class: &KeywordClass,
 ) -> HashMap<String, Vec<(usize, String)>> {
     let mut items_by_needle: HashMap<String, Vec<(usize, String)>> = HashMap::new();
-    let keyword_range = keyword::get_keyword_range(line, cursor_col, match_suffix);
+    let keyword_range = keyword::get_keyword_range(line, cursor_col, match_suffix, keyword_class);
     for (idx, item_text) in haystack.iter().enumerate() {
-        let needle = keyword::guess_keyword(keyword_range, item_text, line);
+        if excluded.get(idx).copied().unwrap_or(false) {
+            continue;
+        }
+        let needle = match needle_starts.get(idx).copied().flatten() {
+            Some(start) => line[start.min(keyword_range.1)..keyword_range.1].to_string(),
+            None => keyword::guess_keyword(keyword_range, item_text, line, keyword_class),
+        };
         let entry = items_by_needle.entry(needle).or_default();
         entry.push((idx, item_text.to_string()));
     }
@@ -92,10 +197,16 @@ pub fn fuzzy<'a>(
     frecency: Option<&FrecencyDB>,
     opts: FuzzyOptions,
 ) -> Vec<FuzzyMatch<'a>> {
+    let excluded = haystack
+        .iter()
+        .map(|item| opts.filter.as_ref().is_some_and(|f| f.excludes(item)))
+        .collect::<Vec<_>>();
     let haystack_labels = haystack
         .iter()
         .map(|s| s.filter_text.clone().unwrap_or(s.label.clone()))
         .collect::<Vec<_>>();
+    let needle_starts =
+        server_needle_starts(haystack, line, opts.match_suffix, &opts.offset_encodings);
     let config = frizbee::Config {
         max_typos: Some(opts.max_typos),
         sort: false,
@@ -103,92 +214,257 @@ pub fn fuzzy<'a>(
     };
 
     // Items may have different fuzzy matching ranges, so we split them up by needle
-    let matches = group_by_needle(line, cursor_col, &haystack_labels, opts.match_suffix)
-        .into_iter()
-        // Match on each needle and combine
-        .flat_map(|(needle, haystack)| {
-            let mut matches = frizbee::match_list(
-                &needle,
-                &haystack
-                    .iter()
-                    .map(|(_, str)| str.as_str())
-                    .collect::<Vec<_>>(),
-                &config,
-            );
-            for mtch in matches.iter_mut() {
-                mtch.index = haystack[mtch.index as usize].0 as u32;
+    let mut matches = group_by_needle(
+        line,
+        cursor_col,
+        &haystack_labels,
+        &needle_starts,
+        &excluded,
+        opts.match_suffix,
+        &opts.keyword_class,
+    )
+    .into_iter()
+    // Match on each needle and combine
+    .flat_map(|(needle, group)| {
+        let mut matches = frizbee::match_list(
+            &needle,
+            &group
+                .iter()
+                .map(|(_, str)| str.as_str())
+                .collect::<Vec<_>>(),
+            &config,
+        );
+        for mtch in matches.iter_mut() {
+            mtch.index = group[mtch.index as usize].0 as u32;
+        }
+        matches
+    })
+    .collect::<Vec<_>>();
+
+    // Keep the best of the label and snippet prefix matches for each snippet
+    let mut match_idx_by_item = matches
+        .iter()
+        .enumerate()
+        .map(|(match_idx, mtch)| (mtch.index, match_idx))
+        .collect::<HashMap<_, _>>();
+    let snippet_matches = match_snippet_prefixes(
+        line,
+        cursor_col,
+        haystack,
+        &needle_starts,
+        &excluded,
+        &opts,
+        &config,
+    );
+    for snippet_match in snippet_matches {
+        match match_idx_by_item.get(&snippet_match.index) {
+            Some(&match_idx) => {
+                let mtch = &mut matches[match_idx];
+                mtch.exact |= snippet_match.exact;
+                if snippet_match.score > mtch.score {
+                    mtch.score = snippet_match.score;
+                }
             }
-            matches
-        })
-        .collect::<Vec<_>>();
+            None => {
+                match_idx_by_item.insert(snippet_match.index, matches.len());
+                matches.push(snippet_match);
+            }
+        }
+    }
 
-    // Get the score for each match, adding score_offset, frecency and proximity bonus
-    let nearby_words: HashSet<String> = HashSet::from_iter(opts.nearby_words.unwrap_or_default());
+    // Get the score for each match, adding score_offset, frecency, proximity and boost bonus
+    let nearby_words = opts.nearby_words.unwrap_or_default();
     matches
         .into_iter()
         .map(|mtch| {
             let frecency_score = frecency
                 .map(|frecency| {
+                    let item = &haystack[mtch.index as usize];
                     frecency
-                        .get_score(&(&haystack[mtch.index as usize]).into())
+                        .get_versioned_score(&item.into(), &item.legacy_hash())
                         .unwrap_or(0.)
                         .min(6.)
                         .round() as i32
                 })
                 .unwrap_or(0);
+            // Proximity and boosts are both keyed on the text used for matching
+            let label = &haystack_labels[mtch.index as usize];
             let nearby_words_score = if opts.use_proximity {
                 nearby_words
-                    .get(&haystack_labels[mtch.index as usize])
-                    .map(|_| 2)
+                    .get(label)
+                    .map(|distance| proximity_score(*distance))
                     .unwrap_or(0)
             } else {
                 0
             };
+            let boost_score = opts
+                .boosts
+                .as_ref()
+                .and_then(|boosts| boosts.get(label))
+                .copied()
+                .unwrap_or(0);
+            let bonus_score =
+                (nearby_words_score + boost_score).min(opts.max_boost.unwrap_or(i32::MAX));
             let mut score_offset = haystack[mtch.index as usize].score_offset;
-            // 15 = snippet
-            // TODO: use an enum for the kind
-            if haystack[mtch.index as usize].kind == 15 {
+            if haystack[mtch.index as usize].is_snippet() {
                 score_offset += opts.snippet_score_offset;
             }
+            if haystack[mtch.index as usize].deprecated {
+                score_offset += opts.deprecated_score_offset;
+            }
 
             FuzzyMatch {
                 provider_idx,
                 item: &haystack[mtch.index as usize],
-                score: (mtch.score as i32) + frecency_score + nearby_words_score + score_offset,
+                score: (mtch.score as i32) + frecency_score + bonus_score + score_offset,
                 mtch,
+                merged: vec![],
             }
         })
+        .filter(|mtch| {
+            opts.filter
+                .as_ref()
+                .is_none_or(|filter| filter.keeps_score(mtch.score))
+        })
         .collect::<Vec<_>>()
 }
 
+/// Snippets may insert text unrelated to their label, i.e. `console.log($0)` for `log`, so they're
+/// additionally matched on their literal prefix, using the text from the guessed start of the
+/// insertion as the needle. A snippet matches exactly when the needle is its whole prefix.
+/// Snippets with a server provided range are only matched on their label
+fn match_snippet_prefixes(
+    line: &str,
+    cursor_col: usize,
+    haystack: &[LspItem],
+    needle_starts: &[Option<usize>],
+    excluded: &[bool],
+    opts: &FuzzyOptions,
+    config: &frizbee::Config,
+) -> Vec<Match> {
+    let keyword_range =
+        keyword::get_keyword_range(line, cursor_col, opts.match_suffix, &opts.keyword_class);
+    haystack
+        .iter()
+        .enumerate()
+        .filter(|(idx, item)| {
+            item.is_snippet()
+                && !excluded.get(*idx).copied().unwrap_or(false)
+                && needle_starts.get(*idx).copied().flatten().is_none()
+        })
+        .filter_map(|(idx, item)| {
+            let prefix = item.snippet_prefix.as_deref()?;
+            let (start, _) =
+                edit_range::guess_item_range(item, line, keyword_range, &opts.keyword_class);
+            let needle = &line[start.min(keyword_range.1)..keyword_range.1];
+            if needle.is_empty() {
+                return None;
+            }
+            let mut mtch = frizbee::match_list(needle, &[prefix], config)
+                .into_iter()
+                .next()?;
+            mtch.index = idx as u32;
+            mtch.exact = prefix == needle;
+            Some(mtch)
+        })
+        .collect()
+}
+
+/// Arguments of `fuzzy_matched_indices` from Lua: the line, cursor column, haystack, whether to
+/// match the suffix, the keyword class and optionally, the items of the haystack with the
+/// encodings of their LSP clients
+pub type MatchedIndicesArgs = (
+    mlua::String,
+    usize,
+    Vec<mlua::String>,
+    bool,
+    Option<KeywordClass>,
+    Option<Vec<LspItem>>,
+    Option<HashMap<u32, PositionEncoding>>,
+);
+
+/// When the items are passed alongside the haystack, items with a server provided range use the
+/// text from the start of the range as the needle, the same as `fuzzy`
+pub fn fuzzy_matched_indices_from_lua(
+    (line, cursor_col, haystack, match_suffix, keyword_class, items, offset_encodings): MatchedIndicesArgs,
+) -> Vec<Vec<usize>> {
+    let line = line.to_string_lossy();
+    let needle_starts = server_needle_starts(
+        &items.unwrap_or_default(),
+        &line,
+        match_suffix,
+        &offset_encodings.unwrap_or_default(),
+    );
+    fuzzy_matched_indices(
+        &line,
+        cursor_col,
+        &haystack
+            .iter()
+            .map(|s| s.to_string_lossy())
+            .collect::<Vec<_>>(),
+        &needle_starts,
+        match_suffix,
+        &keyword_class.unwrap_or_default(),
+    )
+}
+
+/// Start of the needle for each item, from the range provided by the server via `textEdit`
+pub fn server_needle_starts(
+    items: &[LspItem],
+    line: &str,
+    match_suffix: bool,
+    offset_encodings: &HashMap<u32, PositionEncoding>,
+) -> Vec<Option<usize>> {
+    items
+        .iter()
+        .map(|item| {
+            item.server_edit_range(line, match_suffix, item.offset_encoding(offset_encodings))
+                .map(|(start, _)| start)
+        })
+        .collect()
+}
+
+/// Gets the indices of the characters in each haystack matched by the needle. Items with a known
+/// start column (see `server_needle_starts`) use the text from that column as the needle, the
+/// same as `fuzzy`
 pub fn fuzzy_matched_indices(
     line: &str,
     cursor_col: usize,
     haystack: &[String],
+    needle_starts: &[Option<usize>],
     match_suffix: bool,
+    keyword_class: &KeywordClass,
 ) -> Vec<Vec<usize>> {
     let config = frizbee::Config {
         max_typos: None,
         sort: false,
         ..Default::default()
     };
-    let mut matches = group_by_needle(line, cursor_col, haystack, match_suffix)
-        .into_iter()
-        .flat_map(|(needle, haystack)| {
-            let needle = needle.as_str();
-            haystack
-                .into_iter()
-                .map(|(idx, haystack)| {
-                    (
-                        idx,
-                        frizbee::match_indices(needle, haystack, &config)
-                            .map(|m| m.indices)
-                            .unwrap_or_default(),
-                    )
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect::<Vec<_>>();
+    let mut matches = group_by_needle(
+        line,
+        cursor_col,
+        haystack,
+        needle_starts,
+        &[],
+        match_suffix,
+        keyword_class,
+    )
+    .into_iter()
+    .flat_map(|(needle, haystack)| {
+        let needle = needle.as_str();
+        haystack
+            .into_iter()
+            .map(|(idx, haystack)| {
+                (
+                    idx,
+                    frizbee::match_indices(needle, haystack, &config)
+                        .map(|m| m.indices)
+                        .unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>();
     matches.sort_by_key(|mtch| mtch.0);
 
     matches
@@ -196,3 +472,259 @@ pub fn fuzzy_matched_indices(
         .map(|(_, matched_indices)| matched_indices)
         .collect::<Vec<_>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_item::{CompletionItemKind, LspPosition, LspRange, TextEditRanges};
+
+    fn snippet(label: &str, insert_text: &str) -> LspItem {
+        LspItem {
+            label: label.to_string(),
+            kind: CompletionItemKind::Snippet,
+            insert_text: Some(insert_text.to_string()),
+            insert_text_format: Some(2),
+            snippet_prefix: LspItem::snippet_prefix_of(Some(insert_text), Some(2)),
+            ..Default::default()
+        }
+    }
+
+    /// Item with a `textEdit` on the first line, starting at the given column
+    fn text_edit_item(start: u32, end: u32, client_id: Option<u32>) -> LspItem {
+        let position = |character| LspPosition { line: 0, character };
+        let range = LspRange {
+            start: position(start),
+            end: position(end),
+        };
+        LspItem {
+            label: "foo".to_string(),
+            text_edit_ranges: Some(TextEditRanges {
+                insert: range,
+                replace: range,
+            }),
+            client_id,
+            ..Default::default()
+        }
+    }
+
+    fn items(labels: &[(&str, Option<&str>)]) -> Vec<LspItem> {
+        labels
+            .iter()
+            .map(|(label, filter_text)| LspItem {
+                label: label.to_string(),
+                filter_text: filter_text.map(|s| s.to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn scores(haystack: &[LspItem], opts: FuzzyOptions) -> Vec<i32> {
+        let mut matches = fuzzy(0, "foo", 3, haystack, None, opts);
+        matches.sort_by_key(|m| m.mtch.index);
+        matches.iter().map(|m| m.score).collect()
+    }
+
+    #[test]
+    fn test_merge_nearby_words() {
+        type Words = &'static [(&'static str, Option<u32>)];
+        let cases: &[(Words, Words)] = &[
+            (&[], &[]),
+            (
+                &[("foo", None), ("bar", None)],
+                &[("foo", None), ("bar", None)],
+            ),
+            (
+                &[("foo", Some(3)), ("bar", Some(12))],
+                &[("foo", Some(3)), ("bar", Some(12))],
+            ),
+            // known distances take precedence over unknown ones
+            (&[("foo", None), ("foo", Some(5))], &[("foo", Some(5))]),
+            (&[("foo", Some(5)), ("foo", None)], &[("foo", Some(5))]),
+            // the closest distance is kept
+            (&[("foo", Some(5)), ("foo", Some(1))], &[("foo", Some(1))]),
+        ];
+        for (words, expected) in cases {
+            let words = words.iter().map(|(word, d)| (word.to_string(), *d));
+            let expected = expected
+                .iter()
+                .map(|(word, d)| (word.to_string(), *d))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(merge_nearby_words(words), expected);
+        }
+    }
+
+    #[test]
+    fn test_proximity_score() {
+        let cases = [
+            (None, 2),
+            (Some(0), 3),
+            (Some(1), 3),
+            (Some(2), 2),
+            (Some(10), 2),
+            (Some(11), 1),
+            (Some(u32::MAX), 1),
+        ];
+        for (distance, expected) in cases {
+            assert_eq!(
+                proximity_score(distance),
+                expected,
+                "distance {:?}",
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn test_proximity_bonus_by_distance() {
+        let haystack = items(&[
+            ("foo", None),
+            ("foobar", None),
+            ("food", None),
+            ("fool", None),
+        ]);
+        let base = scores(&haystack, FuzzyOptions::default());
+        let nearby_words = HashMap::from([
+            ("foo".to_string(), None),
+            ("foobar".to_string(), Some(1)),
+            ("food".to_string(), Some(10)),
+            ("fool".to_string(), Some(11)),
+        ]);
+        let scored = scores(
+            &haystack,
+            FuzzyOptions {
+                use_proximity: true,
+                nearby_words: Some(nearby_words),
+                ..Default::default()
+            },
+        );
+        let bonuses = scored
+            .iter()
+            .zip(&base)
+            .map(|(a, b)| a - b)
+            .collect::<Vec<_>>();
+        assert_eq!(bonuses, vec![2, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_server_needle_starts_encoding() {
+        // `é` is 2 bytes in utf-8 but a single code unit in utf-16 and utf-32
+        let line = "é.fo";
+        let items = [
+            // Snippets and paths use byte columns
+            text_edit_item(3, 5, None),
+            // LSP clients default to utf-16
+            text_edit_item(2, 4, Some(1)),
+            text_edit_item(2, 4, Some(2)),
+            text_edit_item(3, 5, Some(3)),
+        ];
+        let offset_encodings =
+            HashMap::from([(2, PositionEncoding::Utf32), (3, PositionEncoding::Utf8)]);
+        assert_eq!(
+            server_needle_starts(&items, line, false, &offset_encodings),
+            vec![Some(3); 4]
+        );
+    }
+
+    #[test]
+    fn test_matched_indices_with_needle_starts() {
+        let line = "foo.ba";
+        let haystack = vec!["foo.bar".to_string()];
+        let keyword_class = KeywordClass::default();
+
+        // Without a server range, the needle is guessed to include `foo.`
+        let guessed = fuzzy_matched_indices(line, 6, &haystack, &[], false, &keyword_class);
+        assert_eq!(guessed, vec![vec![0, 1, 2, 3, 4, 5]]);
+
+        // The server range only covers `ba`, the same needle used when fuzzy matching
+        let starts =
+            server_needle_starts(&[text_edit_item(4, 6, None)], line, false, &HashMap::new());
+        let indices = fuzzy_matched_indices(line, 6, &haystack, &starts, false, &keyword_class);
+        assert_eq!(indices, vec![vec![4, 5]]);
+    }
+
+    #[test]
+    fn test_snippets() {
+        let haystack = [
+            // Matches on the label with the keyword as the needle
+            snippet("log", "console.log($0)"),
+            // Matches on the prefix with the text since the start of the insertion as the needle
+            snippet("clg", "console.log($0)"),
+            snippet("cl", "console.lo"),
+            snippet("foo", "bar"),
+        ];
+        let line = "  console.lo";
+        let matches = fuzzy(
+            0,
+            line,
+            line.len(),
+            &haystack,
+            None,
+            FuzzyOptions::default(),
+        );
+        let mut matches = matches
+            .iter()
+            .map(|m| (m.item.label.as_str(), m.mtch.exact))
+            .collect::<Vec<_>>();
+        matches.sort();
+        assert_eq!(matches, vec![("cl", true), ("clg", false), ("log", false)]);
+    }
+
+    #[test]
+    fn test_deprecated_score_offset() {
+        let mut haystack = items(&[("foo", None), ("food", None)]);
+        haystack[1].deprecated = true;
+        let base = scores(&haystack, FuzzyOptions::default());
+        let demoted = scores(
+            &haystack,
+            FuzzyOptions {
+                deprecated_score_offset: -5,
+                ..Default::default()
+            },
+        );
+        assert_eq!(demoted, vec![base[0], base[1] - 5]);
+    }
+
+    #[test]
+    fn test_boosts() {
+        let haystack = items(&[("foo", None), ("bar", Some("foobar"))]);
+        let base = scores(&haystack, FuzzyOptions::default());
+        let boosts = HashMap::from([
+            ("foo".to_string(), 10),
+            ("foobar".to_string(), 2),
+            // keyed on the filter text, so the label is ignored
+            ("bar".to_string(), 100),
+        ]);
+
+        let boosted = scores(
+            &haystack,
+            FuzzyOptions {
+                boosts: Some(boosts.clone()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(boosted, vec![base[0] + 10, base[1] + 2]);
+
+        let clamped = scores(
+            &haystack,
+            FuzzyOptions {
+                boosts: Some(boosts.clone()),
+                max_boost: Some(4),
+                ..Default::default()
+            },
+        );
+        assert_eq!(clamped, vec![base[0] + 4, base[1] + 2]);
+
+        // proximity and boosts are clamped together
+        let with_proximity = scores(
+            &haystack,
+            FuzzyOptions {
+                use_proximity: true,
+                nearby_words: Some(HashMap::from([("foobar".to_string(), Some(0))])),
+                boosts: Some(boosts),
+                max_boost: Some(4),
+                ..Default::default()
+            },
+        );
+        assert_eq!(with_proximity, vec![base[0] + 4, base[1] + 4]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+use crate::frecency::FrecencyStats;
+use crate::lsp_item::LspItem;
+use crate::matcher::Matcher;
+use crate::recovery::{self, RecoveryStats};
+
+/// Number of recent fuzzy calls kept for reporting
+const MAX_TIMINGS: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzyTiming {
+    pub duration: Duration,
+    /// Number of items across all providers
+    pub items: usize,
+    /// Number of matches, `None` when cancelled
+    pub matches: Option<usize>,
+    pub is_async: bool,
+}
+
+impl FuzzyTiming {
+    pub fn new(
+        start: Instant,
+        haystacks: &[&[LspItem]],
+        matches: Option<usize>,
+        is_async: bool,
+    ) -> Self {
+        FuzzyTiming {
+            duration: start.elapsed(),
+            items: haystacks.iter().map(|haystack| haystack.len()).sum(),
+            matches,
+            is_async,
+        }
+    }
+}
+
+impl IntoLua for FuzzyTiming {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("duration_ms", self.duration.as_secs_f64() * 1000.)?;
+        tab.set("items", self.items)?;
+        tab.set("matches", self.matches)?;
+        tab.set("cancelled", self.matches.is_none())?;
+        tab.set("async", self.is_async)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+/// Timings of the most recent fuzzy calls
+#[derive(Debug, Default)]
+pub struct FuzzyTimings(Mutex<VecDeque<FuzzyTiming>>);
+
+impl FuzzyTimings {
+    pub fn record(&self, timing: FuzzyTiming) {
+        let mut timings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if timings.len() == MAX_TIMINGS {
+            timings.pop_front();
+        }
+        timings.push_back(timing);
+    }
+
+    /// Most recent first
+    pub fn recent(&self) -> Vec<FuzzyTiming> {
+        let timings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        timings.iter().rev().copied().collect()
+    }
+}
+
+impl IntoLua for FrecencyStats {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("path", self.path.to_string_lossy().as_ref())?;
+        tab.set("entries", self.entries)?;
+        tab.set("file_size", self.file_size)?;
+        tab.set("decoded_cleanly", self.decoded_cleanly)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+/// Diagnostics shown by `:checkhealth blink.cmp`
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// `None` when the frecency database hasn't been initialized
+    pub frecency: Option<FrecencyStats>,
+    /// Error from reading the frecency database's stats, i.e. when the file was deleted
+    pub frecency_error: Option<String>,
+    pub providers: usize,
+    pub items: usize,
+    pub bytes: usize,
+    pub fuzzy_timings: Vec<FuzzyTiming>,
+    pub recovery: RecoveryStats,
+}
+
+impl Health {
+    pub fn collect(matcher: &Matcher) -> Self {
+        let (frecency, frecency_error) = match matcher.frecency().as_ref().map(|db| db.stats()) {
+            Some(Ok(stats)) => (Some(stats), None),
+            Some(Err(err)) => (None, Some(err.to_string())),
+            None => (None, None),
+        };
+        let memory = matcher.items().stats();
+
+        Health {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("BLINK_CMP_GIT_SHA"),
+            frecency,
+            frecency_error,
+            providers: memory
+                .by_namespace
+                .values()
+                .map(|providers| providers.len())
+                .sum(),
+            items: memory.items,
+            bytes: memory.bytes,
+            fuzzy_timings: matcher.timings().recent(),
+            recovery: recovery::stats(),
+        }
+    }
+}
+
+impl IntoLua for Health {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("version", self.version)?;
+        tab.set("git_sha", self.git_sha)?;
+        tab.set("frecency", self.frecency)?;
+        tab.set("frecency_error", self.frecency_error)?;
+        tab.set("providers", self.providers)?;
+        tab.set("items", self.items)?;
+        tab.set("bytes", self.bytes)?;
+        tab.set("fuzzy_timings", self.fuzzy_timings)?;
+        tab.set("recovery", self.recovery)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_timings() {
+        let timings = FuzzyTimings::default();
+        for matches in 0..MAX_TIMINGS + 2 {
+            timings.record(FuzzyTiming {
+                duration: Duration::from_millis(1),
+                items: 10,
+                matches: Some(matches),
+                is_async: false,
+            });
+        }
+
+        let recent = timings.recent();
+        assert_eq!(recent.len(), MAX_TIMINGS);
+        assert_eq!(recent[0].matches, Some(MAX_TIMINGS + 1));
+        assert_eq!(recent[MAX_TIMINGS - 1].matches, Some(2));
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use mlua::prelude::*;
+
+use crate::error::Error;
+use crate::lsp_item::LspItem;
+
+/// Caps on the items retained across all namespaces and providers. When exceeded, the least
+/// recently used haystacks are evicted when setting items and on the next match
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ItemLimits {
+    pub max_items: Option<usize>,
+    /// Approximate, see `LspItem::size_in_bytes`
+    pub max_bytes: Option<usize>,
+}
+
+impl FromLua for ItemLimits {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let Some(tab) = value.as_table() {
+            Ok(ItemLimits {
+                max_items: tab.get("max_items")?,
+                max_bytes: tab.get("max_bytes")?,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "ItemLimits".to_string(),
+                message: None,
+            })
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Haystack {
+    /// Shared with in-flight async matches, which are matching against a snapshot of the items
+    items: Arc<Vec<LspItem>>,
+    bytes: usize,
+    /// Value of the store's clock when the haystack was last read or written
+    last_used: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HaystackStats {
+    pub items: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStats {
+    pub items: usize,
+    pub bytes: usize,
+    pub max_items: Option<usize>,
+    pub max_bytes: Option<usize>,
+    /// Number of haystacks evicted to stay within the limits
+    pub evictions: u64,
+    /// Stats by namespace, then by provider
+    pub by_namespace: HashMap<String, HashMap<String, HaystackStats>>,
+}
+
+impl IntoLua for HaystackStats {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("items", self.items)?;
+        tab.set("bytes", self.bytes)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+impl IntoLua for MemoryStats {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("items", self.items)?;
+        tab.set("bytes", self.bytes)?;
+        tab.set("max_items", self.max_items)?;
+        tab.set("max_bytes", self.max_bytes)?;
+        tab.set("evictions", self.evictions)?;
+        tab.set("by_namespace", self.by_namespace)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+/// Namespace used when the caller doesn't provide one
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// (namespace, provider id)
+type HaystackKey = (String, String);
+
+fn key(namespace: &str, provider_id: &str) -> HaystackKey {
+    (namespace.to_string(), provider_id.to_string())
+}
+
+/// Items of each provider, stored separately for each namespace (i.e. completion context) so
+/// that contexts sharing a provider don't overwrite each other. Optionally evicts the least
+/// recently used haystacks across all namespaces. Setting items only evicts the haystacks of
+/// other namespaces, and matching those of the providers not being matched, so that the
+/// haystacks uploaded for a match aren't evicted by each other
+#[derive(Debug, Default)]
+pub struct ItemStore {
+    haystacks: HashMap<HaystackKey, Haystack>,
+    limits: ItemLimits,
+    /// Incremented on every use of a haystack, so that reads only need a shared reference
+    clock: AtomicU64,
+    evictions: u64,
+}
+
+impl ItemStore {
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn get(&self, namespace: &str, provider_id: &str) -> Option<&[LspItem]> {
+        let haystack = self.haystacks.get(&key(namespace, provider_id))?;
+        haystack.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(&haystack.items)
+    }
+
+    /// Same as `get` but returns a snapshot of the items which doesn't borrow the store
+    pub fn get_shared(&self, namespace: &str, provider_id: &str) -> Option<Arc<Vec<LspItem>>> {
+        let haystack = self.haystacks.get(&key(namespace, provider_id))?;
+        haystack.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(haystack.items.clone())
+    }
+
+    pub fn set(&mut self, namespace: &str, provider_id: &str, items: Vec<LspItem>) {
+        let haystack = Haystack {
+            bytes: items.iter().map(|item| item.size_in_bytes()).sum(),
+            items: Arc::new(items),
+            last_used: AtomicU64::new(self.tick()),
+        };
+        self.haystacks.insert(key(namespace, provider_id), haystack);
+        self.evict_other_namespaces(namespace);
+    }
+
+    /// Applies the update to the provider's items, returning `None` if the provider has no items.
+    /// The update adjusts the haystack's bytes by the size of the items it adds and removes, so
+    /// that small updates don't need to walk the whole haystack
+    pub fn update<T>(
+        &mut self,
+        namespace: &str,
+        provider_id: &str,
+        update: impl FnOnce(&mut Vec<LspItem>, &mut usize) -> T,
+    ) -> Option<T> {
+        let tick = self.tick();
+        let haystack = self.haystacks.get_mut(&key(namespace, provider_id))?;
+        let result = update(Arc::make_mut(&mut haystack.items), &mut haystack.bytes);
+        haystack.last_used.store(tick, Ordering::Relaxed);
+        Some(result)
+    }
+
+    /// Appends to the provider's items, setting them if the provider has no items yet
+    pub fn append(&mut self, namespace: &str, provider_id: &str, mut items: Vec<LspItem>) {
+        let appended = self
+            .update(namespace, provider_id, |haystack, bytes| {
+                *bytes += items.iter().map(|item| item.size_in_bytes()).sum::<usize>();
+                haystack.append(&mut items)
+            })
+            .is_some();
+        if appended {
+            self.evict_other_namespaces(namespace);
+        } else {
+            self.set(namespace, provider_id, items);
+        }
+    }
+
+    /// Removes the items at the given 0-indexed positions, shifting the following items down.
+    /// Positions past the end are ignored
+    pub fn remove_items(
+        &mut self,
+        namespace: &str,
+        provider_id: &str,
+        indices: &HashSet<usize>,
+    ) -> Result<(), Error> {
+        self.update(namespace, provider_id, |haystack, bytes| {
+            let mut idx = 0;
+            haystack.retain(|item| {
+                let keep = !indices.contains(&idx);
+                if !keep {
+                    *bytes -= item.size_in_bytes();
+                }
+                idx += 1;
+                keep
+            });
+        })
+        .ok_or_else(|| Error::UpdateBeforeSetItems {
+            namespace: namespace.to_string(),
+            provider_id: provider_id.to_string(),
+        })
+    }
+
+    /// Replaces the items at the given 0-indexed positions. Nothing is replaced if any of the
+    /// positions are out of bounds
+    pub fn patch_items(
+        &mut self,
+        namespace: &str,
+        provider_id: &str,
+        patches: HashMap<usize, LspItem>,
+    ) -> Result<(), Error> {
+        self.update(namespace, provider_id, |haystack, bytes| {
+            if let Some(&index) = patches.keys().find(|&&index| index >= haystack.len()) {
+                return Err(Error::ItemIndexOutOfBounds {
+                    provider_id: provider_id.to_string(),
+                    index,
+                    len: haystack.len(),
+                });
+            }
+            for (index, item) in patches {
+                *bytes = *bytes + item.size_in_bytes() - haystack[index].size_in_bytes();
+                haystack[index] = item;
+            }
+            Ok(())
+        })
+        .ok_or_else(|| Error::UpdateBeforeSetItems {
+            namespace: namespace.to_string(),
+            provider_id: provider_id.to_string(),
+        })?
+    }
+
+    pub fn remove(&mut self, namespace: &str, provider_id: &str) {
+        self.haystacks.remove(&key(namespace, provider_id));
+    }
+
+    pub fn remove_namespace(&mut self, namespace: &str) {
+        self.haystacks.retain(|(ns, _), _| ns != namespace);
+    }
+
+    pub fn clear(&mut self) {
+        self.haystacks = HashMap::new();
+    }
+
+    pub fn set_limits(&mut self, limits: ItemLimits) {
+        self.limits = limits;
+        self.evict(&[]);
+    }
+
+    /// Evicts the least recently used haystacks until within the limits, keeping the haystacks of
+    /// the providers being matched, even if they alone exceed the limits
+    pub fn evict_unused(&mut self, namespace: &str, provider_ids: &[String]) {
+        let keep = provider_ids
+            .iter()
+            .map(|provider_id| key(namespace, provider_id))
+            .collect::<Vec<_>>();
+        self.evict(&keep);
+    }
+
+    /// Evicts the least recently used haystacks of other namespaces until within the limits, so
+    /// that setting the items of one provider can't evict the other providers of the same match
+    fn evict_other_namespaces(&mut self, namespace: &str) {
+        if !self.exceeds_limits() {
+            return;
+        }
+        let keep = self
+            .haystacks
+            .keys()
+            .filter(|(ns, _)| ns == namespace)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.evict(&keep);
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let mut by_namespace: HashMap<String, HashMap<String, HaystackStats>> = HashMap::new();
+        for ((namespace, provider_id), haystack) in &self.haystacks {
+            by_namespace.entry(namespace.clone()).or_default().insert(
+                provider_id.clone(),
+                HaystackStats {
+                    items: haystack.items.len(),
+                    bytes: haystack.bytes,
+                },
+            );
+        }
+        MemoryStats {
+            items: self.haystacks.values().map(|h| h.items.len()).sum(),
+            bytes: self.haystacks.values().map(|h| h.bytes).sum(),
+            max_items: self.limits.max_items,
+            max_bytes: self.limits.max_bytes,
+            evictions: self.evictions,
+            by_namespace,
+        }
+    }
+
+    fn exceeds_limits(&self) -> bool {
+        let stats = || self.haystacks.values();
+        self.limits
+            .max_items
+            .is_some_and(|max| stats().map(|h| h.items.len()).sum::<usize>() > max)
+            || self
+                .limits
+                .max_bytes
+                .is_some_and(|max| stats().map(|h| h.bytes).sum::<usize>() > max)
+    }
+
+    /// Evicts the least recently used haystacks, other than the `keep` haystacks, until within
+    /// the limits
+    fn evict(&mut self, keep: &[HaystackKey]) {
+        while self.exceeds_limits() {
+            let Some(key) = self
+                .haystacks
+                .iter()
+                .filter(|(key, _)| !keep.contains(key))
+                .min_by_key(|(_, haystack)| haystack.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone())
+            else {
+                return;
+            };
+            self.haystacks.remove(&key);
+            self.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NS: &str = DEFAULT_NAMESPACE;
+
+    fn items(count: usize) -> Vec<LspItem> {
+        (0..count)
+            .map(|i| LspItem {
+                label: format!("item{}", i),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn provider_ids(store: &ItemStore, namespace: &str) -> Vec<String> {
+        let mut provider_ids = store
+            .stats()
+            .by_namespace
+            .remove(namespace)
+            .unwrap_or_default()
+            .into_keys()
+            .collect::<Vec<_>>();
+        provider_ids.sort();
+        provider_ids
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = ItemStore::default();
+        store.set_limits(ItemLimits {
+            max_items: Some(10),
+            max_bytes: None,
+        });
+
+        store.set(NS, "a", items(4));
+        store.set(NS, "b", items(4));
+        // Reading "a" makes "b" the least recently used
+        store.get(NS, "a");
+        store.set(NS, "c", items(4));
+        // Setting doesn't evict within the namespace, so that the providers of a match can't
+        // evict each other
+        assert_eq!(provider_ids(&store, NS), vec!["a", "b", "c"]);
+        store.evict_unused(NS, &["c".to_string()]);
+        assert_eq!(provider_ids(&store, NS), vec!["a", "c"]);
+        assert_eq!(store.stats().evictions, 1);
+
+        // Growing "c" past the limit evicts everything else, but not "c" itself
+        store.append(NS, "c", items(8));
+        store.evict_unused(NS, &["c".to_string()]);
+        assert_eq!(provider_ids(&store, NS), vec!["c"]);
+        assert_eq!(store.stats().items, 12);
+        assert!(store.get(NS, "c").is_some());
+        assert!(store.update(NS, "missing", |_, _| ()).is_none());
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let mut store = ItemStore::default();
+        store.set(NS, "a", items(2));
+        let bytes = store.stats().bytes;
+        assert!(bytes > 0);
+
+        store.set(NS, "b", items(2));
+        store.set_limits(ItemLimits {
+            max_items: None,
+            max_bytes: Some(bytes),
+        });
+        assert_eq!(provider_ids(&store, NS), vec!["b"]);
+    }
+
+    #[test]
+    fn test_evict_keeps_matched_providers() {
+        let mut store = ItemStore::default();
+        store.set_limits(ItemLimits {
+            max_items: Some(4),
+            max_bytes: None,
+        });
+        store.set("other", "a", items(4));
+        store.set(NS, "a", items(4));
+        store.set(NS, "b", items(4));
+
+        // Setting the items only evicts the haystacks of other namespaces, even though the limit
+        // is still exceeded
+        assert!(provider_ids(&store, "other").is_empty());
+        assert_eq!(provider_ids(&store, NS), vec!["a", "b"]);
+        assert_eq!(store.stats().evictions, 1);
+
+        // Matching keeps the matched providers
+        store.evict_unused(NS, &["a".to_string(), "b".to_string()]);
+        assert_eq!(provider_ids(&store, NS), vec!["a", "b"]);
+        store.evict_unused(NS, &["b".to_string()]);
+        assert_eq!(provider_ids(&store, NS), vec!["b"]);
+        assert_eq!(store.stats().evictions, 2);
+    }
+
+    /// Asserts the tracked bytes match the size of the items
+    fn assert_bytes(store: &ItemStore) {
+        let bytes = store
+            .haystacks
+            .values()
+            .flat_map(|haystack| haystack.items.iter())
+            .map(|item| item.size_in_bytes())
+            .sum::<usize>();
+        assert_eq!(store.stats().bytes, bytes);
+    }
+
+    fn labels(store: &ItemStore, provider_id: &str) -> Vec<String> {
+        store
+            .get(NS, provider_id)
+            .unwrap_or_default()
+            .iter()
+            .map(|item| item.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_remove_items() {
+        let mut store = ItemStore::default();
+        store.set(NS, "lsp", items(5));
+
+        // The following items shift down and positions past the end are ignored
+        store
+            .remove_items(NS, "lsp", &HashSet::from([1, 3, 10]))
+            .unwrap();
+        assert_eq!(labels(&store, "lsp"), vec!["item0", "item2", "item4"]);
+        assert_bytes(&store);
+
+        // Positions refer to the items after the previous removal
+        store.remove_items(NS, "lsp", &HashSet::from([1])).unwrap();
+        assert_eq!(labels(&store, "lsp"), vec!["item0", "item4"]);
+
+        assert!(matches!(
+            store.remove_items(NS, "missing", &HashSet::from([0])),
+            Err(Error::UpdateBeforeSetItems { .. })
+        ));
+    }
+
+    #[test]
+    fn test_patch_items() {
+        let mut store = ItemStore::default();
+        store.set(NS, "lsp", items(3));
+        let item = |label: &str| LspItem {
+            label: label.to_string(),
+            ..Default::default()
+        };
+
+        store
+            .patch_items(
+                NS,
+                "lsp",
+                HashMap::from([(0, item("foo")), (2, item("bar"))]),
+            )
+            .unwrap();
+        assert_eq!(labels(&store, "lsp"), vec!["foo", "item1", "bar"]);
+        assert_bytes(&store);
+
+        // Out of bounds patches fail without applying any of the patches
+        let result = store.patch_items(
+            NS,
+            "lsp",
+            HashMap::from([(1, item("baz")), (3, item("qux"))]),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::ItemIndexOutOfBounds {
+                index: 3,
+                len: 3,
+                ..
+            })
+        ));
+        assert_eq!(labels(&store, "lsp"), vec!["foo", "item1", "bar"]);
+
+        assert!(matches!(
+            store.patch_items(NS, "missing", HashMap::new()),
+            Err(Error::UpdateBeforeSetItems { .. })
+        ));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let mut store = ItemStore::default();
+        store.set(NS, "lsp", items(2));
+        store.set("cmdline", "lsp", items(3));
+        assert_eq!(store.get(NS, "lsp").map(|items| items.len()), Some(2));
+        assert_eq!(
+            store.get("cmdline", "lsp").map(|items| items.len()),
+            Some(3)
+        );
+
+        store.remove_namespace("cmdline");
+        assert!(store.get("cmdline", "lsp").is_none());
+        assert_eq!(provider_ids(&store, NS), vec!["lsp"]);
+    }
+}
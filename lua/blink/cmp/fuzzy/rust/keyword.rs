@@ -1,28 +1,202 @@
-use std::sync::LazyLock;
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
 
+use mlua::prelude::*;
 use regex::Regex;
 
-static NON_KEYWORD_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[^\p{L}0-9_-]").unwrap());
-static BACKWARD_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"[\p{L}0-9_][\p{L}0-9_-]*$").unwrap());
-static FORWARD_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\p{L}0-9_-]+").unwrap());
+use crate::error::Error;
+use crate::recovery;
+use crate::regex_cache::get_regex;
+
+/// Characters considered part of a keyword when no language specific class has been registered
+const DEFAULT_CLASS: &str = r"\p{L}0-9_\-";
+
+static DEFAULT_KEYWORD_CLASS: LazyLock<KeywordClass> =
+    LazyLock::new(|| KeywordClass::new(DEFAULT_CLASS).unwrap());
+static COMBINING_MARK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\p{M}$").unwrap());
+static CJK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[\p{Han}\p{Hiragana}\p{Katakana}]$").unwrap());
+static KEYWORD_CLASS_BY_LANGUAGE: LazyLock<RwLock<HashMap<String, KeywordClass>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Set of characters making up a keyword, i.e. `\p{L}0-9_\-` for letters, digits, underscores
+/// and hyphens. A lone hyphen is never considered a keyword
+#[derive(Debug, Clone)]
+pub struct KeywordClass {
+    non_keyword: Regex,
+    backward: Regex,
+    forward: Regex,
+}
+
+impl Default for KeywordClass {
+    fn default() -> Self {
+        DEFAULT_KEYWORD_CLASS.clone()
+    }
+}
+
+impl KeywordClass {
+    /// Creates a keyword class from the contents of a regex character class, i.e. `\p{L}0-9_$`
+    pub fn new(class: &str) -> Result<Self, Error> {
+        Ok(KeywordClass {
+            non_keyword: get_regex(&format!("[^{class}]"))?,
+            backward: get_regex(&format!("[[{class}]--[\\-]][{class}]*$"))?,
+            forward: get_regex(&format!("^[{class}]+"))?,
+        })
+    }
+
+    /// Creates a keyword class from Vim's `iskeyword` option, i.e. `@,48-57,_,192-255`
+    /// See `:h isfname` for the format
+    pub fn from_iskeyword(iskeyword: &str) -> Result<Self, Error> {
+        let mut includes = vec![];
+        let mut excludes = vec![];
+
+        // Commas may appear as a part, i.e. `a,,,b`, so split manually
+        let mut parts = vec![];
+        let mut part = String::new();
+        for c in iskeyword.chars() {
+            if c == ',' && !part.is_empty() && part != "^" {
+                parts.push(std::mem::take(&mut part));
+            } else {
+                part.push(c);
+            }
+        }
+        if !part.is_empty() {
+            parts.push(part);
+        }
+
+        for part in parts {
+            let (part, target) = match part.strip_prefix('^') {
+                Some(rest) if !rest.is_empty() => (rest.to_string(), &mut excludes),
+                _ => (part, &mut includes),
+            };
+            target.push(
+                iskeyword_part_to_class(&part).ok_or_else(|| Error::InvalidIsKeyword {
+                    iskeyword: iskeyword.to_string(),
+                    part: part.clone(),
+                })?,
+            );
+        }
+
+        let includes = includes.concat();
+        if excludes.is_empty() {
+            Self::new(&includes)
+        } else {
+            Self::new(&format!("[{includes}]--[{}]", excludes.concat()))
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        !self.non_keyword.is_match(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Gets the keyword class registered for the language, falling back to the default
+    pub fn for_language(language: &str) -> Self {
+        recovery::read(
+            &KEYWORD_CLASS_BY_LANGUAGE,
+            "keyword_classes",
+            HashMap::clear,
+        )
+        .get(language)
+        .cloned()
+        .unwrap_or_default()
+    }
+
+    pub fn register(language: String, keyword_class: KeywordClass) {
+        recovery::write(
+            &KEYWORD_CLASS_BY_LANGUAGE,
+            "keyword_classes",
+            HashMap::clear,
+        )
+        .insert(language, keyword_class);
+    }
+}
+
+/// Converts a single part of `iskeyword` into the contents of a regex character class
+fn iskeyword_part_to_class(part: &str) -> Option<String> {
+    let to_char = |s: &str| -> Option<char> {
+        match s.parse::<u32>() {
+            Ok(code) => char::from_u32(code),
+            Err(_) => {
+                let mut chars = s.chars();
+                chars.next().filter(|_| chars.next().is_none())
+            }
+        }
+    };
+    let escape = |c: char| format!("\\x{{{:x}}}", c as u32);
+
+    if part == "@" {
+        return Some(r"\p{L}".to_string());
+    }
+    if part == "@-@" {
+        return Some(escape('@'));
+    }
+    // Ranges such as `48-57` or `a-z`, taking care that `-` may itself be a single character
+    if let Some((start, end)) = part
+        .split_once('-')
+        .filter(|(s, e)| !s.is_empty() && !e.is_empty())
+    {
+        return Some(format!(
+            "{}-{}",
+            escape(to_char(start)?),
+            escape(to_char(end)?)
+        ));
+    }
+    to_char(part).map(escape)
+}
+
+/// Accepts either a language name, resolved with the registered keyword classes, a `{ pattern }`
+/// table containing the contents of a regex character class or an `{ iskeyword }` table
+impl FromLua for KeywordClass {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match &value {
+            LuaValue::String(language) => {
+                Ok(KeywordClass::for_language(&language.to_string_lossy()))
+            }
+            LuaValue::Table(tab) => {
+                if let Some(pattern) = tab.get::<Option<String>>("pattern")? {
+                    Ok(KeywordClass::new(&pattern)?)
+                } else if let Some(iskeyword) = tab.get::<Option<String>>("iskeyword")? {
+                    Ok(KeywordClass::from_iskeyword(&iskeyword)?)
+                } else {
+                    Err(mlua::Error::FromLuaConversionError {
+                        from: "table",
+                        to: "KeywordClass".to_string(),
+                        message: Some("Expected either a pattern or iskeyword field".to_string()),
+                    })
+                }
+            }
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "KeywordClass".to_string(),
+                message: None,
+            }),
+        }
+    }
+}
 
 /// Given a line and cursor position, returns the start and end indices of the keyword
-pub fn get_keyword_range(line: &str, col: usize, match_suffix: bool) -> (usize, usize) {
+pub fn get_keyword_range(
+    line: &str,
+    col: usize,
+    match_suffix: bool,
+    keyword_class: &KeywordClass,
+) -> (usize, usize) {
     let col = line
         .char_indices()
         .find(|(idx, _)| *idx >= col)
         .map(|(idx, _)| idx)
         .unwrap_or(line.len());
 
-    let before_match_start = BACKWARD_REGEX
+    let before_match_start = keyword_class
+        .backward
         .find(&line[0..col.min(line.len())])
         .map(|m| m.start());
     if !match_suffix {
         return (before_match_start.unwrap_or(col), col);
     }
 
-    let after_match_end = FORWARD_REGEX
+    let after_match_end = keyword_class
+        .forward
         .find(&line[col.min(line.len())..])
         .map(|m| m.end() + col);
     (
@@ -54,15 +228,17 @@ pub fn guess_keyword_range(
     (og_keyword_start, keyword_end): (usize, usize),
     word: &str,
     line: &str,
+    keyword_class: &KeywordClass,
 ) -> (usize, usize) {
     // No special logic needed if the whole word matches the keyword regex or if we can't go
     // backwards
-    if og_keyword_start == 0 || !NON_KEYWORD_REGEX.is_match(word) {
+    if og_keyword_start == 0 || !keyword_class.non_keyword.is_match(word) {
         return (og_keyword_start, keyword_end);
     }
 
-    let word = word.as_bytes();
-    let line = line.as_bytes();
+    if og_keyword_start > line.len() || !line.is_char_boundary(og_keyword_start) {
+        return (og_keyword_start, keyword_end);
+    }
 
     // Use the keyword regex as the "typical" keyword range
     let mut keyword_start = og_keyword_start;
@@ -70,58 +246,78 @@ pub fn guess_keyword_range(
     // Calculate the range to search backwards (don't go below 0)
     let search_start = og_keyword_start.saturating_sub(word.len());
 
-    // Search backwards from just before the keyword start
-    for idx in (search_start..og_keyword_start).rev() {
-        // Check if this position could be a valid word boundary
-        if !is_valid_word_boundary(line, idx) {
+    // Search backwards from just before the keyword start, only considering char boundaries
+    let chars = line[..og_keyword_start].char_indices().collect::<Vec<_>>();
+    for (i, &(idx, curr)) in chars.iter().enumerate().rev() {
+        if idx < search_start {
+            break;
+        }
+
+        // Check if this position could be a valid word boundary, skipping over combining marks
+        // to find the base character
+        let prev = chars[..i]
+            .iter()
+            .rev()
+            .map(|&(_, c)| c)
+            .find(|&c| !is_combining_mark(c));
+        if !is_valid_word_boundary(prev, curr, keyword_class) {
             continue;
         }
 
         // Abort if we hit whitespace (word boundary)
-        if line[idx].is_ascii_whitespace() {
+        if curr.is_whitespace() {
             break;
         }
 
         // Try to match the completion word starting from this position
-        let match_len = og_keyword_start - idx;
-
-        // Don't try to match more characters than we have in either string
-        if match_len <= word.len() && idx + match_len <= line.len() {
-            let line_substr = &line[idx..idx + match_len];
-            let word_substr = &word[0..match_len];
-
-            if line_substr == word_substr {
-                keyword_start = keyword_start.min(idx);
-            }
+        if word
+            .as_bytes()
+            .starts_with(&line.as_bytes()[idx..og_keyword_start])
+        {
+            keyword_start = idx;
         }
     }
 
     (keyword_start, keyword_end)
 }
 
-pub fn guess_keyword(keyword_range: (usize, usize), word: &str, line: &str) -> String {
-    let (start, end) = guess_keyword_range(keyword_range, word, line);
+pub fn guess_keyword(
+    keyword_range: (usize, usize),
+    word: &str,
+    line: &str,
+    keyword_class: &KeywordClass,
+) -> String {
+    let (start, end) = guess_keyword_range(keyword_range, word, line, keyword_class);
     line[start..end].to_string()
 }
 
-/// Logic taken directly from nvim-cmp
+fn is_combining_mark(c: char) -> bool {
+    COMBINING_MARK_REGEX.is_match(c.encode_utf8(&mut [0; 4]))
+}
+
+/// Logic taken from nvim-cmp, extended to Unicode
 /// https://github.com/hrsh7th/nvim-cmp/blob/b5311ab3ed9c846b585c0c15b7559be131ec4be9/lua/cmp/utils/char.lua#L70
-fn is_valid_word_boundary(text: &[u8], index: usize) -> bool {
-    if index == 0 {
+///
+/// Combining marks are never a boundary since they belong to the preceding character, while
+/// each CJK character is a boundary since words aren't separated by spaces. Characters outside of
+/// the keyword class, and the characters following them, are always boundaries
+fn is_valid_word_boundary(prev: Option<char>, curr: char, keyword_class: &KeywordClass) -> bool {
+    let Some(prev) = prev else {
         return true;
-    }
-
-    if index >= text.len() {
+    };
+    if is_combining_mark(curr) {
         return false;
     }
+    if CJK_REGEX.is_match(curr.encode_utf8(&mut [0; 4])) {
+        return true;
+    }
 
     // Check various semantic boundary conditions
-    let prev = text[index - 1];
-    let curr = text[index];
-    return (!prev.is_ascii_uppercase() && curr.is_ascii_uppercase())
-        || !curr.is_ascii_alphanumeric()
-        || (!prev.is_ascii_alphabetic() && curr.is_ascii_alphabetic())
-        || (!prev.is_ascii_digit() && curr.is_ascii_digit());
+    (!prev.is_uppercase() && curr.is_uppercase())
+        || !keyword_class.contains(curr)
+        || !keyword_class.contains(prev)
+        || (!prev.is_alphabetic() && curr.is_alphabetic())
+        || (!prev.is_numeric() && curr.is_numeric())
 }
 
 #[cfg(test)]
@@ -132,14 +328,21 @@ mod tests {
     fn test_get_keyword_range_unicode() {
         let line = "'вest'";
         let col = line.len() - 1;
-        assert_eq!(get_keyword_range(line, col, false), (1, line.len() - 1));
+        assert_eq!(
+            get_keyword_range(line, col, false, &KeywordClass::default()),
+            (1, line.len() - 1)
+        );
     }
 
     #[test]
     fn test_guess_keyword_range() {
         fn guess_range(line: &str, item: &str, expected: (usize, usize)) {
-            let keyword_range = get_keyword_range(line, line.len(), false);
-            assert_eq!(guess_keyword_range(keyword_range, item, line), expected);
+            let keyword_class = KeywordClass::default();
+            let keyword_range = get_keyword_range(line, line.len(), false, &keyword_class);
+            assert_eq!(
+                guess_keyword_range(keyword_range, item, line, &keyword_class),
+                expected
+            );
         }
 
         guess_range("str/tr", "str/trim", (0, 6));
@@ -154,5 +357,168 @@ mod tests {
         guess_range("'ta", "'tabline'", (0, 3));
         guess_range("guy mon", "guy montag", (4, 7)); // aborts when hitting whitespace
         guess_range("~/.a123", "/.a123456", (1, 7));
+        // `-` is part of the keyword, so `a-` isn't split
+        guess_range("a-/b", "-/bc", (3, 4));
+    }
+
+    #[test]
+    fn test_guess_keyword_range_keyword_class() {
+        fn guess_range(line: &str, item: &str, keyword_class: &KeywordClass) -> (usize, usize) {
+            let keyword_range = get_keyword_range(line, line.len(), false, keyword_class);
+            guess_keyword_range(keyword_range, item, line, keyword_class)
+        }
+
+        // `$` is part of the keyword in PHP, so `x$foo` is a single keyword and `$` isn't a
+        // boundary, while it is in the default class
+        let dollar = KeywordClass::new(r"\p{L}0-9_$").unwrap();
+        assert_eq!(guess_range("x$foo.ba", "$foo.bar", &dollar), (6, 8));
+        assert_eq!(
+            guess_range("x$foo.ba", "$foo.bar", &KeywordClass::default()),
+            (1, 8)
+        );
+
+        // `-` is not part of a keyword in C, so it's a boundary
+        let c = KeywordClass::from_iskeyword("@,48-57,_,192-255").unwrap();
+        assert_eq!(guess_range("a-/b", "-/bc", &c), (1, 4));
+    }
+
+    #[test]
+    fn test_keyword_class() {
+        fn range(line: &str, keyword_class: &KeywordClass) -> (usize, usize) {
+            get_keyword_range(line, line.len(), false, keyword_class)
+        }
+
+        // Shell and PHP variables
+        let dollar = KeywordClass::new(r"\p{L}0-9_$").unwrap();
+        assert_eq!(range("echo $HO", &dollar), (5, 8));
+        assert_eq!(range("echo $HO", &KeywordClass::default()), (6, 8));
+
+        // `-` is not part of a keyword in C
+        let c = KeywordClass::from_iskeyword("@,48-57,_,192-255").unwrap();
+        assert_eq!(range("foo-ba", &c), (4, 6));
+        assert_eq!(range("foo-ba", &KeywordClass::default()), (0, 6));
+
+        // Lisp predicates and earmuffs
+        let lisp = KeywordClass::from_iskeyword("@,48-57,_,?,!,*,-").unwrap();
+        assert_eq!(range("(empty?", &lisp), (1, 7));
+        assert_eq!(range("(*glob", &lisp), (1, 6));
+
+        // Exclusions and literal commas
+        let no_underscore = KeywordClass::from_iskeyword("@,48-57,_,^_,,").unwrap();
+        assert_eq!(range("foo_b,ar", &no_underscore), (4, 8));
+    }
+
+    #[test]
+    fn test_guess_keyword_range_unicode() {
+        fn guess_range(line: &str, item: &str, expected: (usize, usize)) {
+            let keyword_class = KeywordClass::default();
+            let keyword_range = get_keyword_range(line, line.len(), false, &keyword_class);
+            assert_eq!(
+                guess_keyword_range(keyword_range, item, line, &keyword_class),
+                expected
+            );
+        }
+
+        // Multibyte characters before the keyword
+        guess_range("мой/фу", "мой/функция", (0, 11));
+        guess_range("é/tr", "é/trim", (0, 5));
+        // The `é` in the middle of `été` isn't a boundary, even though it isn't ASCII
+        guess_range("été/tr", "é/trim", (6, 8));
+        // Combining marks belong to the preceding character, so the `e` in `e\u{301}` isn't
+        // split from its accent
+        guess_range("e\u{301}/tr", "e\u{301}/trim", (0, 6));
+        guess_range("xe\u{301}/tr", "e\u{301}/trim", (5, 7));
+        // Each CJK character is a boundary
+        guess_range("漢字/tr", "字/trim", (3, 9));
+        guess_range("漢字/tr", "漢字/trim", (0, 9));
+    }
+
+    /// Byte based implementation for the default keyword class, used as a reference for ASCII input
+    fn reference_guess_keyword_range(
+        (og_keyword_start, keyword_end): (usize, usize),
+        word: &str,
+        line: &str,
+    ) -> (usize, usize) {
+        if og_keyword_start == 0 || !KeywordClass::default().non_keyword.is_match(word) {
+            return (og_keyword_start, keyword_end);
+        }
+        let (word, line) = (word.as_bytes(), line.as_bytes());
+        let is_keyword = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'-';
+        let is_boundary = |index: usize| {
+            if index == 0 {
+                return true;
+            }
+            let (prev, curr) = (line[index - 1], line[index]);
+            (!prev.is_ascii_uppercase() && curr.is_ascii_uppercase())
+                || !is_keyword(curr)
+                || !is_keyword(prev)
+                || (!prev.is_ascii_alphabetic() && curr.is_ascii_alphabetic())
+                || (!prev.is_ascii_digit() && curr.is_ascii_digit())
+        };
+
+        let mut keyword_start = og_keyword_start;
+        for idx in (og_keyword_start.saturating_sub(word.len())..og_keyword_start).rev() {
+            if !is_boundary(idx) {
+                continue;
+            }
+            if line[idx].is_ascii_whitespace() {
+                break;
+            }
+            let match_len = og_keyword_start - idx;
+            if line[idx..idx + match_len] == word[0..match_len] {
+                keyword_start = keyword_start.min(idx);
+            }
+        }
+        (keyword_start, keyword_end)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_guess_keyword_range_bounds(line in "\\PC{0,24}", word in "\\PC{0,12}", col in 0usize..100) {
+            let keyword_class = KeywordClass::default();
+            let keyword_range = get_keyword_range(&line, col, false, &keyword_class);
+            let (start, end) = guess_keyword_range(keyword_range, &word, &line, &keyword_class);
+
+            // Only ever extends backwards, landing on a char boundary
+            proptest::prop_assert!(start <= keyword_range.0);
+            proptest::prop_assert_eq!(end, keyword_range.1);
+            proptest::prop_assert!(line.is_char_boundary(start));
+
+            // The extension must match the start of the word
+            if start < keyword_range.0 {
+                proptest::prop_assert!(word.starts_with(&line[start..keyword_range.0]));
+            }
+        }
+
+        #[test]
+        fn prop_guess_keyword_range_matches_reference_for_ascii(
+            line in "[ -~]{0,24}",
+            word in "[ -~]{0,12}",
+            col in 0usize..30,
+        ) {
+            let keyword_class = KeywordClass::default();
+            let keyword_range = get_keyword_range(&line, col, false, &keyword_class);
+            proptest::prop_assert_eq!(
+                guess_keyword_range(keyword_range, &word, &line, &keyword_class),
+                reference_guess_keyword_range(keyword_range, &word, &line)
+            );
+        }
+
+        #[test]
+        fn prop_guess_keyword_range_finds_prefix(
+            prefix in "([\\p{L}0-9_][\\p{L}\\p{M}0-9_]{0,5})?",
+            sep in "[./:~#]",
+            keyword in "\\p{L}{1,6}",
+            rest in "\\p{L}{0,6}",
+        ) {
+            // `prefix` + `sep` + `keyword` typed, completing `prefix` + `sep` + `keyword` + `rest`
+            let line = format!(" {prefix}{sep}{keyword}");
+            let word = format!("{prefix}{sep}{keyword}{rest}");
+            let keyword_class = KeywordClass::default();
+            let keyword_range = get_keyword_range(&line, line.len(), false, &keyword_class);
+            let (start, _) = guess_keyword_range(keyword_range, &word, &line, &keyword_class);
+            proptest::prop_assert!(start <= 1 + prefix.len());
+            proptest::prop_assert!(word.starts_with(&line[start..keyword_range.0]));
+        }
     }
 }
@@ -1,243 +1,540 @@
-use crate::error::Error;
-use crate::frecency::FrecencyDB;
+use crate::dedup::MergedMatch;
+use crate::encoding::PositionEncoding;
+use crate::error::{Error, ErrorInfo, NonThrowing};
 use crate::fuzzy::FuzzyOptions;
+use crate::health::Health;
+use crate::item_store::{ItemLimits, MemoryStats, DEFAULT_NAMESPACE};
+use crate::keyword::KeywordClass;
 use crate::lsp_item::LspItem;
-use crate::sort::Sort;
-use lsp_item::CompletionItemKind;
+use crate::matcher::{FuzzyResult, Matcher, MatcherOptions};
+use crate::snippet::{ExpandedSnippet, SnippetNode};
+use crate::string_filter::{StringFilterOptions, StringFilterResult};
+use crate::task::FuzzyTask;
+use crate::words::{Tokenizer, WordIndex};
 use mlua::prelude::*;
-use regex::Regex;
-use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::sync::{LazyLock, RwLock};
 
+mod dedup;
+mod edit_range;
+mod encoding;
 mod error;
+mod filter;
 mod frecency;
 mod fuzzy;
+mod health;
+mod item_store;
 mod keyword;
 mod lsp_item;
+mod matcher;
+mod packed;
+mod recovery;
+mod regex_cache;
+mod snippet;
 mod sort;
+mod string_filter;
+mod task;
+mod words;
 
-static REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"[\p{L}_][\p{L}0-9_\\-]{2,}").unwrap());
-static FRECENCY: LazyLock<RwLock<Option<FrecencyDB>>> = LazyLock::new(|| RwLock::new(None));
-static HAYSTACKS_BY_PROVIDER: LazyLock<RwLock<HashMap<String, Vec<LspItem>>>> =
+static DEFAULT_MATCHER: LazyLock<Matcher> = LazyLock::new(Matcher::default);
+static WORD_INDEX_BY_BUFFER: LazyLock<RwLock<HashMap<u32, WordIndex>>> =
     LazyLock::new(|| RwLock::new(HashMap::new()));
 
 pub fn init_db(_: &Lua, db_path: String) -> LuaResult<bool> {
-    let mut frecency = FRECENCY.write().map_err(|_| Error::AcquireFrecencyLock)?;
-    if frecency.is_some() {
-        return Ok(false);
-    }
-    *frecency = Some(FrecencyDB::new(&std::path::PathBuf::from(db_path))?);
-    Ok(true)
+    Ok(DEFAULT_MATCHER.init_db(&db_path)?)
 }
 
 pub fn destroy_db(_: &Lua, _: ()) -> LuaResult<bool> {
-    let frecency = FRECENCY.write().map_err(|_| Error::AcquireFrecencyLock)?;
-    drop(frecency);
-
-    let mut frecency = FRECENCY.write().map_err(|_| Error::AcquireFrecencyLock)?;
-    *frecency = None;
-
+    *DEFAULT_MATCHER.frecency_mut() = None;
     Ok(true)
 }
 
 pub fn access(_: &Lua, item: LspItem) -> LuaResult<bool> {
-    let mut frecency = FRECENCY.write().map_err(|_| Error::AcquireFrecencyLock)?;
-    let frecency = frecency.as_mut().ok_or(Error::UseFrecencyBeforeInit)?;
-    frecency.access(&(&item).into())?;
+    DEFAULT_MATCHER.access(&item)?;
     Ok(true)
 }
 
+/// Creates a matcher with its own provider items, independent of the module level functions
+pub fn new_matcher(_: &Lua, opts: MatcherOptions) -> LuaResult<Matcher> {
+    Ok(Matcher::new(&opts, DEFAULT_MATCHER.frecency_handle())?)
+}
+
+/// Sets the items of the provider in the namespace, defaulting to the `default` namespace
 pub fn set_provider_items(
     _: &Lua,
-    (provider_id, items): (String, Vec<LspItem>),
+    (provider_id, items, namespace): (String, Vec<LspItem>, Option<String>),
+) -> LuaResult<bool> {
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER
+        .items_mut()
+        .set(namespace, &provider_id, items);
+    Ok(true)
+}
+
+/// Appends items to the provider's existing items, or sets them if the provider has no items yet
+pub fn append_provider_items(
+    _: &Lua,
+    (provider_id, items, namespace): (String, Vec<LspItem>, Option<String>),
+) -> LuaResult<bool> {
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER
+        .items_mut()
+        .append(namespace, &provider_id, items);
+    Ok(true)
+}
+
+/// Sets the items of the provider from a msgpack encoded array of items, i.e. from
+/// `vim.mpack.encode`, skipping the conversion of each item from a Lua table
+pub fn set_provider_items_packed(
+    _: &Lua,
+    (provider_id, items, namespace): (String, mlua::String, Option<String>),
+) -> LuaResult<bool> {
+    let items = packed::decode_items(&items.as_bytes())?;
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER
+        .items_mut()
+        .set(namespace, &provider_id, items);
+    Ok(true)
+}
+
+/// Removes the items at the given 0-indexed positions, shifting the following items down.
+/// Positions past the end are ignored
+pub fn remove_provider_items(
+    _: &Lua,
+    (provider_id, indices, namespace): (String, Vec<usize>, Option<String>),
+) -> LuaResult<bool> {
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER.items_mut().remove_items(
+        namespace,
+        &provider_id,
+        &indices.into_iter().collect(),
+    )?;
+    Ok(true)
+}
+
+/// Replaces the items at the given 0-indexed positions. Nothing is replaced if any of the
+/// positions are out of bounds
+pub fn patch_provider_items(
+    _: &Lua,
+    (provider_id, patches, namespace): (String, HashMap<usize, LspItem>, Option<String>),
 ) -> LuaResult<bool> {
-    HAYSTACKS_BY_PROVIDER
-        .write()
-        .map_err(|_| Error::AcquireItemLock)?
-        .insert(provider_id, items);
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER
+        .items_mut()
+        .patch_items(namespace, &provider_id, patches)?;
+    Ok(true)
+}
+
+pub fn clear_provider_items(
+    _: &Lua,
+    (provider_id, namespace): (String, Option<String>),
+) -> LuaResult<bool> {
+    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    DEFAULT_MATCHER.items_mut().remove(namespace, &provider_id);
+    Ok(true)
+}
+
+/// Clears the items of all providers in the namespace
+pub fn clear_namespace(_: &Lua, namespace: String) -> LuaResult<bool> {
+    DEFAULT_MATCHER.items_mut().remove_namespace(&namespace);
+    Ok(true)
+}
+
+pub fn clear_all(_: &Lua, _: ()) -> LuaResult<bool> {
+    DEFAULT_MATCHER.items_mut().clear();
     Ok(true)
 }
 
+/// Caps the items retained across all namespaces and providers, evicting the least recently used
+/// haystacks when exceeded. Pass an empty table to remove the limits
+pub fn set_item_limits(_: &Lua, limits: ItemLimits) -> LuaResult<bool> {
+    DEFAULT_MATCHER.items_mut().set_limits(limits);
+    Ok(true)
+}
+
+pub fn memory_stats(_: &Lua, _: ()) -> LuaResult<MemoryStats> {
+    Ok(DEFAULT_MATCHER.items().stats())
+}
+
 pub fn fuzzy(
     _lua: &Lua,
     (line, cursor_col, provider_ids, opts): (mlua::String, usize, Vec<String>, FuzzyOptions),
-) -> LuaResult<(Vec<u16>, Vec<u32>, Vec<i32>, Vec<bool>)> {
-    // Gather static data
-    let frecency = FRECENCY.read().map_err(|_| Error::AcquireFrecencyLock)?;
-    let haystacks_by_provider = HAYSTACKS_BY_PROVIDER
-        .read()
-        .map_err(|_| Error::AcquireItemLock)?;
-
-    // Perform fuzzy matching per provider and combine
-    let mut matches = provider_ids
-        .iter()
-        .enumerate()
-        .map(|(provider_idx, provider_id)| {
-            let haystack = haystacks_by_provider.get(provider_id).ok_or_else(|| {
-                Error::FuzzyBeforeSetItems {
-                    provider_id: provider_id.to_string(),
-                }
-            })?;
-
-            Ok(fuzzy::fuzzy(
-                (provider_idx).try_into().unwrap(),
-                &line.to_string_lossy(),
-                cursor_col,
-                haystack,
-                frecency.as_ref(),
-                opts.clone(),
-            ))
-        })
-        .try_fold(Vec::new(), |mut acc, result: LuaResult<_>| {
-            result.map(|mut vec| {
-                acc.append(&mut vec);
-                acc
-            })
-        })?;
-
-    // Sort by provider idx then index in haystack
-    matches.sort_by_key(|m| (m.provider_idx, m.mtch.index));
-
-    // Sort by user-defined sorts
-    if let Some(sorts) = opts.sorts {
-        matches.sort_by(|a, b| {
-            sorts.iter().fold(Ordering::Equal, |acc, sort| {
-                if acc != Ordering::Equal {
-                    return acc;
-                }
-
-                match sort {
-                    // Reverse ordering
-                    Sort::Exact => b.mtch.exact.cmp(&a.mtch.exact),
-                    Sort::Score => b.score.cmp(&a.score),
-
-                    // Regular ordering
-                    Sort::Kind => a.item.kind.cmp(&b.item.kind),
-                    Sort::SortText => match (&a.item.sort_text, &b.item.sort_text) {
-                        (Some(a), Some(b)) => a.cmp(b),
-                        // Consider results with Some value to be greater than those with None
-                        (Some(_), None) => Ordering::Greater,
-                        (None, Some(_)) => Ordering::Less,
-                        // Neither has sort text
-                        (None, None) => Ordering::Equal,
-                    },
-                    Sort::Label => Sort::label(a.item, b.item),
-                }
-            })
-        })
-    }
-
-    Ok((
-        matches.iter().map(|m| m.provider_idx).collect(),
-        matches.iter().map(|m| m.mtch.index).collect(),
-        matches.iter().map(|m| m.score).collect(),
-        matches.iter().map(|m| m.mtch.exact).collect(),
-    ))
+) -> LuaResult<FuzzyResult> {
+    Ok(DEFAULT_MATCHER.fuzzy(&line.to_string_lossy(), cursor_col, &provider_ids, opts)?)
 }
 
-pub fn fuzzy_matched_indices(
+/// Same as `fuzzy` but matches on a worker thread, returning a task to poll for the result.
+/// Cancels the previous async matches
+pub fn fuzzy_async(
     _lua: &Lua,
-    (line, cursor_col, haystack, match_suffix): (mlua::String, usize, Vec<mlua::String>, bool),
-) -> LuaResult<Vec<Vec<usize>>> {
-    Ok(fuzzy::fuzzy_matched_indices(
+    (line, cursor_col, provider_ids, opts): (mlua::String, usize, Vec<String>, FuzzyOptions),
+) -> LuaResult<FuzzyTask> {
+    Ok(DEFAULT_MATCHER.fuzzy_async(line.to_string_lossy(), cursor_col, &provider_ids, opts)?)
+}
+
+/// Same as `fuzzy` but returns the matches as a single packed string, see
+/// `packed::encode_matches`, followed by the merged matches when deduplicating
+pub fn fuzzy_packed(
+    lua: &Lua,
+    (line, cursor_col, provider_ids, opts): (mlua::String, usize, Vec<String>, FuzzyOptions),
+) -> LuaResult<(mlua::String, Option<Vec<Vec<MergedMatch>>>)> {
+    let dedup = opts.dedup.is_some();
+    let (bytes, merged) = DEFAULT_MATCHER.with_matches(
         &line.to_string_lossy(),
         cursor_col,
-        &haystack
+        &provider_ids,
+        opts,
+        |matches| {
+            (
+                packed::encode_matches(matches),
+                dedup.then(|| matches.iter().map(|m| m.merged.clone()).collect()),
+            )
+        },
+    )?;
+    Ok((lua.create_string(bytes)?, merged))
+}
+
+/// Fuzzy matches the needle against arbitrary strings, i.e. for pickers or menus
+pub fn filter(
+    _lua: &Lua,
+    (needle, strings, opts): (mlua::String, Vec<mlua::String>, StringFilterOptions),
+) -> LuaResult<StringFilterResult> {
+    Ok(string_filter::filter(
+        &needle.to_string_lossy(),
+        &strings
             .iter()
             .map(|s| s.to_string_lossy())
             .collect::<Vec<_>>(),
-        match_suffix,
+        &opts,
     ))
 }
 
+pub fn fuzzy_matched_indices(
+    _lua: &Lua,
+    args: fuzzy::MatchedIndicesArgs,
+) -> LuaResult<Vec<Vec<usize>>> {
+    Ok(fuzzy::fuzzy_matched_indices_from_lua(args))
+}
+
 pub fn get_keyword_range(
     _lua: &Lua,
-    (line, col, match_suffix): (mlua::String, usize, bool),
+    (line, col, match_suffix, keyword_class): (mlua::String, usize, bool, Option<KeywordClass>),
 ) -> LuaResult<(usize, usize)> {
     Ok(keyword::get_keyword_range(
         &line.to_string_lossy(),
         col,
         match_suffix,
+        &keyword_class.unwrap_or_default(),
     ))
 }
 
 pub fn guess_edit_range(
     _lua: &Lua,
-    (item, line, cursor_col, match_suffix): (LspItem, mlua::String, usize, bool),
+    (item, line, cursor_col, match_suffix, keyword_class, offset_encoding): (
+        LspItem,
+        mlua::String,
+        usize,
+        bool,
+        Option<KeywordClass>,
+        Option<PositionEncoding>,
+    ),
 ) -> LuaResult<(usize, usize)> {
     let line_str = line.to_string_lossy();
+    let keyword_class = keyword_class.unwrap_or_default();
+
+    let keyword_range =
+        keyword::get_keyword_range(&line_str, cursor_col, match_suffix, &keyword_class);
+
+    // Prefer the range provided by the server, only extending the end to the keyword since the
+    // user may have typed more characters since the request
+    // Without an explicit encoding, the encoding is resolved from the item's client
+    let offset_encoding = offset_encoding.unwrap_or_else(|| item.offset_encoding(&HashMap::new()));
+    if let Some((start, end)) = item.server_edit_range(&line_str, match_suffix, offset_encoding) {
+        return Ok((start.min(keyword_range.1), end.max(keyword_range.1)));
+    }
 
-    let keyword_range = keyword::get_keyword_range(&line_str, cursor_col, match_suffix);
-    let label_edit_range = keyword::guess_keyword_range(keyword_range, &item.label, &line_str);
-    let filter_text_edit_range = item
-        .filter_text
-        .as_ref()
-        .map(|filter_text| keyword::guess_keyword_range(keyword_range, filter_text, &line_str))
-        .unwrap_or(label_edit_range);
-    let insert_text_edit_range = item
-        .insert_text
-        .as_ref()
-        .map(|insert_text| keyword::guess_keyword_range(keyword_range, insert_text, &line_str))
-        .unwrap_or(filter_text_edit_range);
-
-    // Prefer the insert text, then filter text, then label ranges for non-snippets
-    if item.kind != CompletionItemKind::Snippet as u32 {
-        return Ok(insert_text_edit_range);
+    Ok(edit_range::guess_item_range(
+        &item,
+        &line_str,
+        keyword_range,
+        &keyword_class,
+    ))
+}
+
+/// Guesses the edit range given a window of lines around the cursor, joining lines which
+/// continue each other such as method chains broken across lines. The rows are 0-indexed into
+/// `lines` and the ranges returned as (start_row, start_col, end_row, end_col)
+pub fn guess_multiline_edit_range(
+    _lua: &Lua,
+    (item, lines, cursor_row, cursor_col, match_suffix, keyword_class): (
+        LspItem,
+        Vec<mlua::String>,
+        usize,
+        usize,
+        bool,
+        Option<KeywordClass>,
+    ),
+) -> LuaResult<(usize, usize, usize, usize)> {
+    let lines = lines
+        .iter()
+        .map(|line| line.to_string_lossy())
+        .collect::<Vec<_>>();
+    if cursor_row >= lines.len() {
+        return Err(Error::CursorOutOfBounds {
+            cursor_row,
+            line_count: lines.len(),
+        }
+        .into());
     }
 
-    // HACK: In the lazydev.nvim case, the label is the whole module like `blink.cmp.fuzzy`
-    // but the `insertText` is just `fuzzy` when you've already typed `blink.cmp.`.
-    // But in the snippets case, the label could be completed unrelated to the insertText so we
-    // should use the label range.
-    //
-    // TODO: What about using the filterText range and ignoring label?
-
-    // Take the max range prioritizing the start index first and the end index second
-    // When comparing tuples (start, end), Rust compares the first element first,
-    // and only if those are equal, it compares the second element
-    Ok([
-        label_edit_range,
-        filter_text_edit_range,
-        insert_text_edit_range,
-    ]
-    .iter()
-    // Transform to (start, -end) to find minimum start and maximum end
-    .min_by_key(|&(start, end)| (start, std::cmp::Reverse(end)))
-    .copied()
-    .unwrap_or((0, 0)))
-}
-
-pub fn get_words(_: &Lua, text: mlua::String) -> LuaResult<Vec<String>> {
-    Ok(REGEX
-        .find_iter(&text.to_string_lossy())
-        .map(|m| m.as_str().to_string())
-        .filter(|s| s.len() < 512)
+    Ok(edit_range::guess_multiline_item_range(
+        &item,
+        &lines.iter().map(|line| line.as_str()).collect::<Vec<_>>(),
+        cursor_row,
+        cursor_col,
+        match_suffix,
+        &keyword_class.unwrap_or_default(),
+    ))
+}
+
+pub fn get_words(
+    _: &Lua,
+    (text, tokenizer): (mlua::String, Option<Tokenizer>),
+) -> LuaResult<Vec<String>> {
+    Ok(tokenizer
+        .unwrap_or_default()
+        .find_words(&text.to_string_lossy())
+        .map(|s| s.to_string())
         .collect::<HashSet<String>>()
         .into_iter()
         .collect())
 }
 
+/// Gets the words on each line, along with the distance of the closest line containing the word
+/// from the line at `cursor_idx`
+pub fn get_nearby_words(
+    _: &Lua,
+    (lines, cursor_idx, tokenizer): (Vec<mlua::String>, usize, Option<Tokenizer>),
+) -> LuaResult<HashMap<String, usize>> {
+    let lines = lines
+        .iter()
+        .map(|line| line.to_string_lossy())
+        .collect::<Vec<_>>();
+    Ok(WordIndex::new(&lines, tokenizer.unwrap_or_default()).nearby_words(cursor_idx, usize::MAX))
+}
+
+/// Indexes the words of the whole buffer, replacing any existing index for the buffer
+pub fn set_buffer_lines(
+    _: &Lua,
+    (bufnr, lines, tokenizer): (u32, Vec<mlua::String>, Option<Tokenizer>),
+) -> LuaResult<bool> {
+    let lines = lines
+        .iter()
+        .map(|line| line.to_string_lossy())
+        .collect::<Vec<_>>();
+    recovery::write(&WORD_INDEX_BY_BUFFER, "word_index", HashMap::clear)
+        .insert(bufnr, WordIndex::new(&lines, tokenizer.unwrap_or_default()));
+    Ok(true)
+}
+
+/// Replaces the lines in `[start_row, old_end_row)` (0-indexed) with `new_lines`, re-tokenizing
+/// only the changed lines
+pub fn update_buffer_lines(
+    _: &Lua,
+    (bufnr, start_row, old_end_row, new_lines): (u32, usize, usize, Vec<mlua::String>),
+) -> LuaResult<bool> {
+    let new_lines = new_lines
+        .iter()
+        .map(|line| line.to_string_lossy())
+        .collect::<Vec<_>>();
+    recovery::write(&WORD_INDEX_BY_BUFFER, "word_index", HashMap::clear)
+        .get_mut(&bufnr)
+        .ok_or(Error::UpdateBeforeSetBufferLines { bufnr })?
+        .update(start_row, old_end_row, &new_lines);
+    Ok(true)
+}
+
+pub fn clear_buffer_lines(_: &Lua, bufnr: u32) -> LuaResult<bool> {
+    Ok(
+        recovery::write(&WORD_INDEX_BY_BUFFER, "word_index", HashMap::clear)
+            .remove(&bufnr)
+            .is_some(),
+    )
+}
+
+/// Gets the unique words of the buffer, or nil if the buffer hasn't been indexed
+pub fn get_buffer_words(_: &Lua, bufnr: u32) -> LuaResult<Option<Vec<String>>> {
+    Ok(
+        recovery::read(&WORD_INDEX_BY_BUFFER, "word_index", HashMap::clear)
+            .get(&bufnr)
+            .map(|index| index.words().cloned().collect()),
+    )
+}
+
+/// Gets the words within `radius` lines of `cursor_row` (0-indexed) along with their distance
+/// from the cursor, or nil if the buffer hasn't been indexed
+pub fn get_buffer_nearby_words(
+    _: &Lua,
+    (bufnr, cursor_row, radius): (u32, usize, usize),
+) -> LuaResult<Option<HashMap<String, usize>>> {
+    Ok(
+        recovery::read(&WORD_INDEX_BY_BUFFER, "word_index", HashMap::clear)
+            .get(&bufnr)
+            .map(|index| index.nearby_words(cursor_row, radius)),
+    )
+}
+
+/// Converts a column in the line between `utf-8` (bytes), `utf-16` and `utf-32` encodings
+pub fn convert_col(
+    _: &Lua,
+    (line, col, from, to): (mlua::String, usize, PositionEncoding, PositionEncoding),
+) -> LuaResult<usize> {
+    Ok(encoding::convert_col(
+        &line.to_string_lossy(),
+        col,
+        from,
+        to,
+    ))
+}
+
+/// Parses the LSP/TextMate snippet body into its nodes, keeping invalid syntax as text
+pub fn parse_snippet(_: &Lua, body: mlua::String) -> LuaResult<Vec<SnippetNode>> {
+    Ok(snippet::parse(&body.to_string_lossy()))
+}
+
+pub fn expand_snippet(
+    _: &Lua,
+    (body, variables): (mlua::String, Option<HashMap<String, String>>),
+) -> LuaResult<ExpandedSnippet> {
+    Ok(snippet::expand(
+        &snippet::parse(&body.to_string_lossy()),
+        &variables.unwrap_or_default(),
+    ))
+}
+
+/// Registers the keyword class used when passing the language's name as the keyword class
+pub fn register_keyword_class(
+    _: &Lua,
+    (language, keyword_class): (String, KeywordClass),
+) -> LuaResult<bool> {
+    KeywordClass::register(language, keyword_class);
+    Ok(true)
+}
+
+/// Converts an error raised by this module, i.e. caught with `pcall`, into a table with a stable
+/// `code`, the `message` and the `context`, such as the provider id
+pub fn error_info(_: &Lua, err: mlua::Error) -> LuaResult<ErrorInfo> {
+    Ok(ErrorInfo::from(&err))
+}
+
+pub fn health(_: &Lua, _: ()) -> LuaResult<Health> {
+    Ok(Health::collect(&DEFAULT_MATCHER))
+}
+
+/// Creates the Lua function, converting panics into errors
+fn create_function<A, R, F>(lua: &Lua, func: F) -> LuaResult<LuaFunction>
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&Lua, A) -> LuaResult<R> + 'static,
+{
+    lua.create_function(move |lua, args: A| recovery::catch_panic(|| func(lua, args)))
+}
+
+/// Wraps the function to return `nil, err` instead of raising, with `err` as in `error_info`
+fn non_throwing(lua: &Lua, func: LuaFunction) -> LuaResult<LuaFunction> {
+    lua.create_function(move |_, args: LuaMultiValue| {
+        Ok(NonThrowing::from(func.call::<LuaMultiValue>(args)))
+    })
+}
+
 // NOTE: skip_memory_check greatly improves performance
 // https://github.com/mlua-rs/mlua/issues/318
 #[mlua::lua_module(skip_memory_check)]
 fn blink_cmp_fuzzy(lua: &Lua) -> LuaResult<LuaTable> {
     let exports = lua.create_table()?;
-    exports.set("init_db", lua.create_function(init_db)?)?;
-    exports.set("destroy_db", lua.create_function(destroy_db)?)?;
-    exports.set("access", lua.create_function(access)?)?;
+    exports.set("init_db", create_function(lua, init_db)?)?;
+    exports.set("destroy_db", create_function(lua, destroy_db)?)?;
+    exports.set("access", create_function(lua, access)?)?;
+    exports.set("new_matcher", create_function(lua, new_matcher)?)?;
     exports.set(
         "set_provider_items",
-        lua.create_function(set_provider_items)?,
+        create_function(lua, set_provider_items)?,
     )?;
-    exports.set("fuzzy", lua.create_function(fuzzy)?)?;
+    exports.set(
+        "append_provider_items",
+        create_function(lua, append_provider_items)?,
+    )?;
+    exports.set(
+        "set_provider_items_packed",
+        create_function(lua, set_provider_items_packed)?,
+    )?;
+    exports.set(
+        "remove_provider_items",
+        create_function(lua, remove_provider_items)?,
+    )?;
+    exports.set(
+        "patch_provider_items",
+        create_function(lua, patch_provider_items)?,
+    )?;
+    exports.set(
+        "clear_provider_items",
+        create_function(lua, clear_provider_items)?,
+    )?;
+    exports.set("clear_namespace", create_function(lua, clear_namespace)?)?;
+    exports.set("clear_all", create_function(lua, clear_all)?)?;
+    exports.set("set_item_limits", create_function(lua, set_item_limits)?)?;
+    exports.set("memory_stats", create_function(lua, memory_stats)?)?;
+    exports.set("fuzzy", create_function(lua, fuzzy)?)?;
+    exports.set("fuzzy_async", create_function(lua, fuzzy_async)?)?;
+    exports.set("fuzzy_packed", create_function(lua, fuzzy_packed)?)?;
+    exports.set("filter", create_function(lua, filter)?)?;
     exports.set(
         "fuzzy_matched_indices",
-        lua.create_function(fuzzy_matched_indices)?,
+        create_function(lua, fuzzy_matched_indices)?,
+    )?;
+    exports.set(
+        "get_keyword_range",
+        create_function(lua, get_keyword_range)?,
+    )?;
+    exports.set("guess_edit_range", create_function(lua, guess_edit_range)?)?;
+    exports.set(
+        "guess_multiline_edit_range",
+        create_function(lua, guess_multiline_edit_range)?,
+    )?;
+    exports.set("convert_col", create_function(lua, convert_col)?)?;
+    exports.set(
+        "register_keyword_class",
+        create_function(lua, register_keyword_class)?,
     )?;
-    exports.set("get_keyword_range", lua.create_function(get_keyword_range)?)?;
-    exports.set("guess_edit_range", lua.create_function(guess_edit_range)?)?;
-    exports.set("get_words", lua.create_function(get_words)?)?;
+    exports.set("parse_snippet", create_function(lua, parse_snippet)?)?;
+    exports.set("expand_snippet", create_function(lua, expand_snippet)?)?;
+    exports.set("get_words", create_function(lua, get_words)?)?;
+    exports.set("get_nearby_words", create_function(lua, get_nearby_words)?)?;
+    exports.set("set_buffer_lines", create_function(lua, set_buffer_lines)?)?;
+    exports.set(
+        "update_buffer_lines",
+        create_function(lua, update_buffer_lines)?,
+    )?;
+    exports.set(
+        "clear_buffer_lines",
+        create_function(lua, clear_buffer_lines)?,
+    )?;
+    exports.set("get_buffer_words", create_function(lua, get_buffer_words)?)?;
+    exports.set(
+        "get_buffer_nearby_words",
+        create_function(lua, get_buffer_nearby_words)?,
+    )?;
+
+    // Same functions, but returning `nil, err` instead of raising errors
+    let non_throwing_exports = lua.create_table()?;
+    for pair in exports.pairs::<String, LuaFunction>() {
+        let (name, func) = pair?;
+        non_throwing_exports.set(name, non_throwing(lua, func)?)?;
+    }
+    exports.set("try", non_throwing_exports)?;
+    exports.set("health", create_function(lua, health)?)?;
+    exports.set("error_info", create_function(lua, error_info)?)?;
+
     Ok(exports)
 }
@@ -1,92 +1,474 @@
+use std::collections::HashMap;
+
 use blake3::Hash;
 use mlua::prelude::*;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-#[repr(u32)]
-#[allow(dead_code)]
+use crate::encoding::{self, PositionEncoding};
+use crate::snippet;
+
+#[derive(Debug, Clone, Copy)]
 pub enum CompletionItemKind {
-    Text = 1,
-    Method = 2,
-    Function = 3,
-    Constructor = 4,
-    Field = 5,
-    Variable = 6,
-    Class = 7,
-    Interface = 8,
-    Module = 9,
-    Property = 10,
-    Unit = 11,
-    Value = 12,
-    Enum = 13,
-    Keyword = 14,
-    Snippet = 15,
-    Color = 16,
-    File = 17,
-    Reference = 18,
-    Folder = 19,
-    EnumMember = 20,
-    Constant = 21,
-    Struct = 22,
-    Event = 23,
-    Operator = 24,
-    TypeParameter = 25,
-}
-
-#[derive(Debug, Clone)]
+    Text,
+    Method,
+    Function,
+    Constructor,
+    Field,
+    Variable,
+    Class,
+    Interface,
+    Module,
+    Property,
+    Unit,
+    Value,
+    Enum,
+    Keyword,
+    Snippet,
+    Color,
+    File,
+    Reference,
+    Folder,
+    EnumMember,
+    Constant,
+    Struct,
+    Event,
+    Operator,
+    TypeParameter,
+    /// Kinds outside of the LSP specification, i.e. custom kinds registered by sources
+    Unknown(u32),
+}
+
+impl Default for CompletionItemKind {
+    fn default() -> Self {
+        CompletionItemKind::Unknown(0)
+    }
+}
+
+impl From<u32> for CompletionItemKind {
+    fn from(kind: u32) -> Self {
+        match kind {
+            1 => CompletionItemKind::Text,
+            2 => CompletionItemKind::Method,
+            3 => CompletionItemKind::Function,
+            4 => CompletionItemKind::Constructor,
+            5 => CompletionItemKind::Field,
+            6 => CompletionItemKind::Variable,
+            7 => CompletionItemKind::Class,
+            8 => CompletionItemKind::Interface,
+            9 => CompletionItemKind::Module,
+            10 => CompletionItemKind::Property,
+            11 => CompletionItemKind::Unit,
+            12 => CompletionItemKind::Value,
+            13 => CompletionItemKind::Enum,
+            14 => CompletionItemKind::Keyword,
+            15 => CompletionItemKind::Snippet,
+            16 => CompletionItemKind::Color,
+            17 => CompletionItemKind::File,
+            18 => CompletionItemKind::Reference,
+            19 => CompletionItemKind::Folder,
+            20 => CompletionItemKind::EnumMember,
+            21 => CompletionItemKind::Constant,
+            22 => CompletionItemKind::Struct,
+            23 => CompletionItemKind::Event,
+            24 => CompletionItemKind::Operator,
+            25 => CompletionItemKind::TypeParameter,
+            kind => CompletionItemKind::Unknown(kind),
+        }
+    }
+}
+
+impl From<CompletionItemKind> for u32 {
+    fn from(kind: CompletionItemKind) -> Self {
+        match kind {
+            CompletionItemKind::Text => 1,
+            CompletionItemKind::Method => 2,
+            CompletionItemKind::Function => 3,
+            CompletionItemKind::Constructor => 4,
+            CompletionItemKind::Field => 5,
+            CompletionItemKind::Variable => 6,
+            CompletionItemKind::Class => 7,
+            CompletionItemKind::Interface => 8,
+            CompletionItemKind::Module => 9,
+            CompletionItemKind::Property => 10,
+            CompletionItemKind::Unit => 11,
+            CompletionItemKind::Value => 12,
+            CompletionItemKind::Enum => 13,
+            CompletionItemKind::Keyword => 14,
+            CompletionItemKind::Snippet => 15,
+            CompletionItemKind::Color => 16,
+            CompletionItemKind::File => 17,
+            CompletionItemKind::Reference => 18,
+            CompletionItemKind::Folder => 19,
+            CompletionItemKind::EnumMember => 20,
+            CompletionItemKind::Constant => 21,
+            CompletionItemKind::Struct => 22,
+            CompletionItemKind::Event => 23,
+            CompletionItemKind::Operator => 24,
+            CompletionItemKind::TypeParameter => 25,
+            CompletionItemKind::Unknown(kind) => kind,
+        }
+    }
+}
+
+/// Compares by the numeric value of the kind, so that i.e. `Unknown(7)` equals `Class`, consistent
+/// with the ordering
+impl PartialEq for CompletionItemKind {
+    fn eq(&self, other: &Self) -> bool {
+        u32::from(*self) == u32::from(*other)
+    }
+}
+
+impl Eq for CompletionItemKind {}
+
+impl std::hash::Hash for CompletionItemKind {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        u32::from(*self).hash(state);
+    }
+}
+
+/// Orders by the numeric value of the kind, as defined by the LSP specification
+impl Ord for CompletionItemKind {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        u32::from(*self).cmp(&u32::from(*other))
+    }
+}
+
+impl PartialOrd for CompletionItemKind {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromLua for CompletionItemKind {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        Ok(u32::from_lua(value, lua)?.into())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LspPosition {
+    pub line: u32,
+    /// Column in the position encoding negotiated with the server, UTF-16 by default
+    pub character: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+/// Ranges of the item's `textEdit`. For a regular `TextEdit`, both ranges are the same
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TextEditRanges {
+    pub insert: LspRange,
+    pub replace: LspRange,
+}
+
+/// Additional details for the label, shown after the label by clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelDetails {
+    /// Shown directly after the label, i.e. function signatures
+    pub detail: Option<String>,
+    /// Shown after the detail, i.e. fully qualified names or file paths
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct LspItem {
     pub label: String,
+    pub label_details: Option<LabelDetails>,
+    pub detail: Option<String>,
     pub filter_text: Option<String>,
     pub sort_text: Option<String>,
     pub insert_text: Option<String>,
-    pub kind: u32,
+    pub insert_text_format: Option<u32>,
+    /// Literal text at the start of the insert text, when the insert text is a snippet
+    pub snippet_prefix: Option<String>,
+    pub text_edit_ranges: Option<TextEditRanges>,
+    pub kind: CompletionItemKind,
+    /// Whether the item is marked as deprecated, either via `deprecated` or the `Deprecated` tag
+    pub deprecated: bool,
+    pub preselect: bool,
+    pub commit_characters: Option<Vec<String>>,
     pub score_offset: i32,
     pub source_id: String,
+    /// LSP client which provided the item, `None` for items from other sources (i.e. snippets
+    /// and paths) whose `textEdit` columns are always in bytes
+    pub client_id: Option<u32>,
 }
 
-impl Into<Hash> for &LspItem {
-    fn into(self) -> Hash {
+/// Version of the item hash used as the frecency key. Bump when changing the hashed fields and
+/// keep the previous scheme in `LspItem::legacy_hash`, so that existing scores carry over
+const HASH_VERSION: u8 = 2;
+
+impl From<&LspItem> for Hash {
+    fn from(item: &LspItem) -> Hash {
         blake3::Hasher::new()
-            .update(&self.label.as_bytes())
-            .update(&[self.kind as u8])
-            .update(&self.source_id.as_bytes())
+            .update(&[HASH_VERSION])
+            .update(&(item.label.len() as u64).to_le_bytes())
+            .update(item.label.as_bytes())
+            .update(&u32::from(item.kind).to_le_bytes())
+            .update(item.source_id.as_bytes())
             .finalize()
     }
 }
 
+impl LspItem {
+    /// Approximate memory used by the item, including its heap allocations
+    pub fn size_in_bytes(&self) -> usize {
+        let string = |s: &String| s.capacity();
+        let optional = |s: &Option<String>| s.as_ref().map_or(0, string);
+        std::mem::size_of::<Self>()
+            + string(&self.label)
+            + self.label_details.as_ref().map_or(0, |details| {
+                optional(&details.detail) + optional(&details.description)
+            })
+            + optional(&self.detail)
+            + optional(&self.filter_text)
+            + optional(&self.sort_text)
+            + optional(&self.insert_text)
+            + optional(&self.snippet_prefix)
+            + self.commit_characters.as_ref().map_or(0, |chars| {
+                chars.capacity() * std::mem::size_of::<String>()
+                    + chars.iter().map(string).sum::<usize>()
+            })
+            + string(&self.source_id)
+    }
+
+    /// Hash from before the hash was versioned, which truncated the kind to a single byte
+    pub fn legacy_hash(&self) -> Hash {
+        blake3::Hasher::new()
+            .update(self.label.as_bytes())
+            .update(&[u32::from(self.kind) as u8])
+            .update(self.source_id.as_bytes())
+            .finalize()
+    }
+
+    /// `CompletionItemTag.Deprecated`
+    pub const DEPRECATED_TAG: u32 = 1;
+    /// `InsertTextFormat.Snippet`
+    const SNIPPET_FORMAT: u32 = 2;
+
+    /// Whether the item is a snippet, by its kind. Note that the insert text of other items may
+    /// also use the snippet format, i.e. functions with placeholders for their arguments, but
+    /// their label always matches the inserted text
+    pub fn is_snippet(&self) -> bool {
+        self.kind == CompletionItemKind::Snippet
+    }
+
+    /// Literal text at the start of the insert text, when the insert text is a snippet
+    pub fn snippet_prefix_of(
+        insert_text: Option<&str>,
+        insert_text_format: Option<u32>,
+    ) -> Option<String> {
+        insert_text
+            .filter(|_| insert_text_format == Some(Self::SNIPPET_FORMAT))
+            .map(snippet::literal_prefix)
+    }
+
+    /// Gets the insert text, with snippet syntax removed. For snippets, this only includes the
+    /// text up to the first tabstop
+    pub fn plain_insert_text(&self) -> Option<&str> {
+        self.snippet_prefix
+            .as_deref()
+            .or(self.insert_text.as_deref())
+    }
+
+    /// Gets the whole insert text with snippet syntax removed, expanding placeholders to their
+    /// default text and tabstops to nothing, i.e. `foo(${1:bar}, $2)` results in `foo(bar, )`
+    pub fn expanded_insert_text(&self) -> Option<String> {
+        let insert_text = self.insert_text.as_deref()?;
+        if self.insert_text_format != Some(Self::SNIPPET_FORMAT) {
+            return Some(insert_text.to_string());
+        }
+        Some(snippet::expand(&snippet::parse(insert_text), &HashMap::new()).text)
+    }
+
+    /// Encoding of the columns in the item's `textEdit` ranges, given the encodings of the LSP
+    /// clients by id. Items without a client use bytes, while unknown clients default to utf-16
+    pub fn offset_encoding(
+        &self,
+        offset_encodings: &HashMap<u32, PositionEncoding>,
+    ) -> PositionEncoding {
+        match self.client_id {
+            Some(client_id) => offset_encodings
+                .get(&client_id)
+                .copied()
+                .unwrap_or_default(),
+            None => PositionEncoding::Utf8,
+        }
+    }
+
+    /// Gets the byte range on the line provided by the server via `textEdit`, if any. Uses the
+    /// replace range when matching the suffix and the insert range otherwise
+    ///
+    /// Ranges spanning multiple lines are ignored since we only know about the current line
+    pub fn server_edit_range(
+        &self,
+        line: &str,
+        match_suffix: bool,
+        offset_encoding: PositionEncoding,
+    ) -> Option<(usize, usize)> {
+        let ranges = self.text_edit_ranges?;
+        let range = if match_suffix {
+            ranges.replace
+        } else {
+            ranges.insert
+        };
+        if range.start.line != range.end.line {
+            return None;
+        }
+
+        let start = encoding::to_byte_col(line, range.start.character as usize, offset_encoding);
+        let end = encoding::to_byte_col(line, range.end.character as usize, offset_encoding);
+        Some((start, end.max(start)))
+    }
+}
+
+/// Reads the fields of a completion item, so that `LspItem::from_fields` builds items the same
+/// way from Lua tables and from packed items (see `packed::decode_items`)
+pub trait ItemFields: Sized {
+    type Error;
+
+    fn string(&self, key: &str) -> Option<String>;
+    /// Fails when the field is present but isn't an unsigned integer
+    fn u32(&self, key: &str) -> Result<Option<u32>, Self::Error>;
+    fn i32(&self, key: &str) -> Option<i32>;
+    fn bool(&self, key: &str) -> bool;
+    fn u32_list(&self, key: &str) -> Vec<u32>;
+    /// `None` when the field is missing or isn't a list of strings
+    fn string_list(&self, key: &str) -> Option<Vec<String>>;
+    /// Fails when the field is present but isn't a table
+    fn table(&self, key: &str) -> Result<Option<Self>, Self::Error>;
+}
+
+impl ItemFields for LuaTable {
+    type Error = LuaError;
+
+    fn string(&self, key: &str) -> Option<String> {
+        self.get::<mlua::String>(key)
+            .ok()
+            .map(|s| s.to_string_lossy())
+    }
+
+    fn u32(&self, key: &str) -> LuaResult<Option<u32>> {
+        self.get(key)
+    }
+
+    fn i32(&self, key: &str) -> Option<i32> {
+        self.get(key).unwrap_or_default()
+    }
+
+    fn bool(&self, key: &str) -> bool {
+        self.get(key).unwrap_or_default()
+    }
+
+    fn u32_list(&self, key: &str) -> Vec<u32> {
+        self.get(key).unwrap_or_default()
+    }
+
+    fn string_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).ok().flatten()
+    }
+
+    fn table(&self, key: &str) -> LuaResult<Option<Self>> {
+        self.get(key)
+    }
+}
+
+impl LabelDetails {
+    fn from_fields<F: ItemFields>(fields: &F) -> Self {
+        LabelDetails {
+            detail: fields.string("detail"),
+            description: fields.string("description"),
+        }
+    }
+}
+
+impl LspPosition {
+    fn from_fields<F: ItemFields>(fields: &F) -> Option<Self> {
+        Some(LspPosition {
+            line: fields.u32("line").ok()??,
+            character: fields.u32("character").ok()??,
+        })
+    }
+}
+
+impl LspRange {
+    fn from_fields<F: ItemFields>(fields: &F) -> Option<Self> {
+        let position = |key| LspPosition::from_fields(&fields.table(key).ok()??);
+        Some(LspRange {
+            start: position("start")?,
+            end: position("end")?,
+        })
+    }
+}
+
+impl TextEditRanges {
+    /// Reads either a `TextEdit` (`range`) or an `InsertReplaceEdit` (`insert` and `replace`)
+    fn from_fields<F: ItemFields>(text_edit: &F) -> Option<Self> {
+        let range = |key| LspRange::from_fields(&text_edit.table(key).ok()??);
+        if let Some(range) = range("range") {
+            return Some(TextEditRanges {
+                insert: range,
+                replace: range,
+            });
+        }
+        Some(TextEditRanges {
+            insert: range("insert")?,
+            replace: range("replace")?,
+        })
+    }
+}
+
+impl LspItem {
+    /// Builds the item from the fields of an LSP completion item, along with the `score_offset`,
+    /// `source_id` and `client_id` set by blink. Invalid fields are ignored, so that a single
+    /// misbehaving source can't fail the whole list, i.e. an invalid `kind` becomes `Unknown(0)`
+    pub fn from_fields<F: ItemFields>(fields: &F) -> Self {
+        let text_edit = fields.table("textEdit").ok().flatten();
+        let insert_text = text_edit
+            .as_ref()
+            .and_then(|text_edit| text_edit.string("newText"))
+            .or_else(|| fields.string("insertText"));
+        let insert_text_format = fields.u32("insertTextFormat").ok().flatten();
+
+        LspItem {
+            label: fields.string("label").unwrap_or_default(),
+            label_details: fields
+                .table("labelDetails")
+                .ok()
+                .flatten()
+                .as_ref()
+                .map(LabelDetails::from_fields),
+            detail: fields.string("detail"),
+            filter_text: fields.string("filterText"),
+            sort_text: fields.string("sortText"),
+            snippet_prefix: LspItem::snippet_prefix_of(insert_text.as_deref(), insert_text_format),
+            insert_text,
+            insert_text_format,
+            text_edit_ranges: text_edit.as_ref().and_then(TextEditRanges::from_fields),
+            kind: fields
+                .u32("kind")
+                .ok()
+                .flatten()
+                .map(Into::into)
+                .unwrap_or_default(),
+            deprecated: fields.bool("deprecated")
+                || fields.u32_list("tags").contains(&LspItem::DEPRECATED_TAG),
+            preselect: fields.bool("preselect"),
+            commit_characters: fields.string_list("commitCharacters"),
+            score_offset: fields.i32("score_offset").unwrap_or(0),
+            source_id: fields.string("source_id").unwrap_or_default(),
+            client_id: fields.u32("client_id").ok().flatten(),
+        }
+    }
+}
+
 impl FromLua for LspItem {
     fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
         if let Some(tab) = value.as_table() {
-            let label = tab
-                .get::<mlua::String>("label")
-                .map(|s| s.to_string_lossy())
-                .unwrap_or_default();
-            let filter_text = tab
-                .get::<mlua::String>("filterText")
-                .ok()
-                .map(|s| s.to_string_lossy());
-            let sort_text = tab
-                .get::<mlua::String>("sortText")
-                .ok()
-                .map(|s| s.to_string_lossy());
-            let insert_text = tab
-                .get::<LuaTable>("textEdit")
-                .and_then(|text_edit| text_edit.get::<mlua::String>("newText"))
-                .ok()
-                .or_else(|| tab.get::<mlua::String>("insertText").ok())
-                .map(|s| s.to_string_lossy());
-            let kind = tab.get("kind").unwrap_or_default();
-            let score_offset = tab.get("score_offset").unwrap_or(0);
-            let source_id = tab.get("source_id").unwrap_or_default();
-
-            Ok(LspItem {
-                label,
-                filter_text,
-                sort_text,
-                insert_text,
-                kind,
-                score_offset,
-                source_id,
-            })
+            Ok(LspItem::from_fields(tab))
         } else {
             Err(mlua::Error::FromLuaConversionError {
                 from: "LuaValue",
@@ -96,3 +478,51 @@ impl FromLua for LspItem {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_item_kind() {
+        for kind in 0..=30 {
+            assert_eq!(u32::from(CompletionItemKind::from(kind)), kind);
+        }
+        assert_eq!(CompletionItemKind::from(15), CompletionItemKind::Snippet);
+        assert_eq!(
+            CompletionItemKind::from(26),
+            CompletionItemKind::Unknown(26)
+        );
+        assert!(CompletionItemKind::Unknown(0) < CompletionItemKind::Text);
+        assert!(CompletionItemKind::TypeParameter < CompletionItemKind::Unknown(26));
+
+        // Known values constructed as `Unknown` are equal to their named variant, consistent
+        // with the ordering and hashing
+        let unknown_class = CompletionItemKind::Unknown(7);
+        assert_eq!(unknown_class, CompletionItemKind::Class);
+        assert_eq!(
+            unknown_class.cmp(&CompletionItemKind::Class),
+            std::cmp::Ordering::Equal
+        );
+        assert_ne!(unknown_class, CompletionItemKind::Interface);
+        let state = std::hash::RandomState::new();
+        assert_eq!(
+            std::hash::BuildHasher::hash_one(&state, unknown_class),
+            std::hash::BuildHasher::hash_one(&state, CompletionItemKind::Class)
+        );
+    }
+
+    #[test]
+    fn test_hash_includes_full_kind() {
+        let item = |kind: u32| LspItem {
+            label: "foo".to_string(),
+            kind: kind.into(),
+            source_id: "lsp".to_string(),
+            ..Default::default()
+        };
+        // Kinds which only differ above the first byte collided in the legacy hash
+        assert_eq!(item(1).legacy_hash(), item(257).legacy_hash());
+        assert_ne!(Hash::from(&item(1)), Hash::from(&item(257)));
+        assert_ne!(Hash::from(&item(1)), item(1).legacy_hash());
+    }
+}
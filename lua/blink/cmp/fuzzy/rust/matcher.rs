@@ -0,0 +1,554 @@
+use std::cmp::Ordering;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+use mlua::prelude::*;
+
+use crate::dedup::{self, MergedMatch};
+use crate::error::Error;
+use crate::frecency::FrecencyDB;
+use crate::fuzzy::{self, FuzzyMatch, FuzzyOptions};
+use crate::health::{FuzzyTiming, FuzzyTimings};
+use crate::item_store::{ItemStore, DEFAULT_NAMESPACE};
+use crate::lsp_item::LspItem;
+use crate::recovery;
+use crate::sort::Sort;
+use crate::task::{FuzzyTask, Worker};
+
+/// Frecency database which may be shared between matchers
+pub type SharedFrecency = Arc<RwLock<Option<FrecencyDB>>>;
+
+/// Provider indices, item indices, scores, exacts and, when deduplicating, the merged matches
+pub type FuzzyResult = (
+    Vec<u16>,
+    Vec<u32>,
+    Vec<i32>,
+    Vec<bool>,
+    Option<Vec<Vec<MergedMatch>>>,
+);
+
+#[derive(Debug, Clone, Default)]
+pub struct MatcherOptions {
+    /// Path of a frecency database owned by the matcher
+    pub db_path: Option<String>,
+    /// Use the frecency database of the default matcher, see `init_db`
+    pub share_frecency: bool,
+}
+
+impl FromLua for MatcherOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(MatcherOptions::default()),
+            LuaValue::Table(tab) => Ok(MatcherOptions {
+                db_path: tab.get("db_path")?,
+                share_frecency: tab.get::<Option<bool>>("share_frecency")?.unwrap_or(false),
+            }),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "MatcherOptions".to_string(),
+                message: None,
+            }),
+        }
+    }
+}
+
+/// Number of items matched between checks for cancellation when matching asynchronously
+const ASYNC_CHUNK_SIZE: usize = 4096;
+
+/// Owns the provider items and, optionally, a frecency database, so that independent completion
+/// UIs don't overwrite each other's state. The module level functions use a default instance.
+/// Clones share the same state
+#[derive(Clone, Default)]
+pub struct Matcher {
+    items: Arc<RwLock<ItemStore>>,
+    frecency: SharedFrecency,
+    /// Incremented on every async match, cancelling the previous async matches
+    generation: Arc<AtomicU64>,
+    worker: Arc<Worker>,
+    timings: Arc<FuzzyTimings>,
+}
+
+impl Matcher {
+    /// Creates a matcher from the options, with `shared_frecency` as the database to share when
+    /// `share_frecency` is set. A matcher can't both share a database and own one
+    pub fn new(opts: &MatcherOptions, shared_frecency: SharedFrecency) -> Result<Self, Error> {
+        if opts.share_frecency && opts.db_path.is_some() {
+            return Err(Error::InvalidMatcherOptions {
+                reason: "share_frecency and db_path are mutually exclusive".to_string(),
+            });
+        }
+        let matcher = if opts.share_frecency {
+            Matcher::with_frecency(shared_frecency)
+        } else {
+            Matcher::default()
+        };
+        if let Some(db_path) = &opts.db_path {
+            matcher.init_db(db_path)?;
+        }
+        Ok(matcher)
+    }
+
+    pub fn with_frecency(frecency: SharedFrecency) -> Self {
+        Matcher {
+            frecency,
+            ..Default::default()
+        }
+    }
+
+    pub fn timings(&self) -> &FuzzyTimings {
+        &self.timings
+    }
+
+    pub fn frecency_handle(&self) -> SharedFrecency {
+        self.frecency.clone()
+    }
+
+    pub fn items(&self) -> RwLockReadGuard<'_, ItemStore> {
+        recovery::read(&self.items, "items", ItemStore::clear)
+    }
+
+    pub fn items_mut(&self) -> RwLockWriteGuard<'_, ItemStore> {
+        recovery::write(&self.items, "items", ItemStore::clear)
+    }
+
+    pub fn frecency(&self) -> RwLockReadGuard<'_, Option<FrecencyDB>> {
+        recovery::read(&self.frecency, "frecency", reset_frecency)
+    }
+
+    pub fn frecency_mut(&self) -> RwLockWriteGuard<'_, Option<FrecencyDB>> {
+        recovery::write(&self.frecency, "frecency", reset_frecency)
+    }
+
+    /// Opens the frecency database, returning false if one is already open
+    pub fn init_db(&self, db_path: &str) -> Result<bool, Error> {
+        let mut frecency = self.frecency_mut();
+        if frecency.is_some() {
+            return Ok(false);
+        }
+        let db_path = PathBuf::from(db_path);
+        *frecency =
+            Some(FrecencyDB::new(&db_path).map_err(|err| err.with_frecency_path(&db_path))?);
+        Ok(true)
+    }
+
+    pub fn access(&self, item: &LspItem) -> Result<(), Error> {
+        let mut frecency = self.frecency_mut();
+        let frecency = frecency.as_mut().ok_or(Error::UseFrecencyBeforeInit)?;
+        frecency
+            .access(&item.into(), &item.legacy_hash())
+            .map_err(|err| err.with_frecency_path(frecency.path()))
+    }
+
+    pub fn fuzzy(
+        &self,
+        line: &str,
+        cursor_col: usize,
+        provider_ids: &[String],
+        opts: FuzzyOptions,
+    ) -> Result<FuzzyResult, Error> {
+        let dedup = opts.dedup.is_some();
+        self.with_matches(line, cursor_col, provider_ids, opts, |matches| {
+            to_fuzzy_result(matches, dedup)
+        })
+    }
+
+    /// Matches the items of the providers, then deduplicates and sorts the matches
+    pub fn with_matches<T>(
+        &self,
+        line: &str,
+        cursor_col: usize,
+        provider_ids: &[String],
+        opts: FuzzyOptions,
+        f: impl FnOnce(&[FuzzyMatch]) -> T,
+    ) -> Result<T, Error> {
+        let namespace = opts.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+        self.items_mut().evict_unused(namespace, provider_ids);
+        let items = self.items();
+        let haystacks = provider_ids
+            .iter()
+            .map(|provider_id| {
+                items
+                    .get(namespace, provider_id)
+                    .ok_or_else(|| fuzzy_before_set_items(namespace, provider_id))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let start = Instant::now();
+        let matches = match_haystacks(
+            line,
+            cursor_col,
+            &haystacks,
+            || self.frecency(),
+            &opts,
+            usize::MAX,
+            || false,
+        );
+        self.timings.record(FuzzyTiming::new(
+            start,
+            &haystacks,
+            matches.as_ref().map(|matches| matches.len()),
+            false,
+        ));
+        Ok(f(&matches.unwrap_or_default()))
+    }
+
+    /// Same as `fuzzy` but matches on the worker thread of this matcher against a snapshot of the
+    /// items. Cancels the previous async matches of this matcher
+    pub fn fuzzy_async(
+        &self,
+        line: String,
+        cursor_col: usize,
+        provider_ids: &[String],
+        opts: FuzzyOptions,
+    ) -> Result<FuzzyTask, Error> {
+        let namespace = opts.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+        self.items_mut().evict_unused(namespace, provider_ids);
+        let haystacks = {
+            let items = self.items();
+            provider_ids
+                .iter()
+                .map(|provider_id| {
+                    items
+                        .get_shared(namespace, provider_id)
+                        .ok_or_else(|| fuzzy_before_set_items(namespace, provider_id))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let generation = self.generation.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+        let current_generation = self.generation.clone();
+        let frecency = self.frecency.clone();
+        let timings = self.timings.clone();
+        let (task, sender) = FuzzyTask::new();
+        let cancelled = task.cancelled.clone();
+
+        self.worker.run(move || {
+            let is_cancelled = || {
+                cancelled.load(AtomicOrdering::Relaxed)
+                    || current_generation.load(AtomicOrdering::Relaxed) != generation
+            };
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let haystacks = haystacks.iter().map(|h| h.as_slice()).collect::<Vec<_>>();
+                let start = Instant::now();
+                let matches = match_haystacks(
+                    &line,
+                    cursor_col,
+                    &haystacks,
+                    || recovery::read(&frecency, "frecency", reset_frecency),
+                    &opts,
+                    ASYNC_CHUNK_SIZE,
+                    is_cancelled,
+                );
+                timings.record(FuzzyTiming::new(
+                    start,
+                    &haystacks,
+                    matches.as_ref().map(|matches| matches.len()),
+                    true,
+                ));
+                matches.map(|matches| to_fuzzy_result(&matches, opts.dedup.is_some()))
+            }))
+            .map_err(recovery::panicked);
+            // The task may have been dropped, in which case nobody is waiting for the result
+            let _ = sender.send(result);
+        })?;
+
+        Ok(task)
+    }
+}
+
+/// Reloads the frecency database from disk, since the cache may have been left half updated
+fn reset_frecency(frecency: &mut Option<FrecencyDB>) {
+    if frecency
+        .as_mut()
+        .is_some_and(|db| db.reload_cache().is_err())
+    {
+        *frecency = None;
+    }
+}
+
+fn fuzzy_before_set_items(namespace: &str, provider_id: &str) -> Error {
+    Error::FuzzyBeforeSetItems {
+        namespace: namespace.to_string(),
+        provider_id: provider_id.to_string(),
+    }
+}
+
+fn to_fuzzy_result(matches: &[FuzzyMatch], dedup: bool) -> FuzzyResult {
+    (
+        matches.iter().map(|m| m.provider_idx).collect(),
+        matches.iter().map(|m| m.mtch.index).collect(),
+        matches.iter().map(|m| m.score).collect(),
+        matches.iter().map(|m| m.mtch.exact).collect(),
+        dedup.then(|| matches.iter().map(|m| m.merged.clone()).collect()),
+    )
+}
+
+/// Matches the haystacks of each provider in chunks of `chunk_size` items, then deduplicates and
+/// sorts the matches. Returns `None` when cancelled, which is checked between chunks. The
+/// frecency database is locked for each chunk, so that accesses aren't blocked by the whole match
+fn match_haystacks<'a, F: Deref<Target = Option<FrecencyDB>>>(
+    line: &str,
+    cursor_col: usize,
+    haystacks: &[&'a [LspItem]],
+    frecency: impl Fn() -> F,
+    opts: &FuzzyOptions,
+    chunk_size: usize,
+    is_cancelled: impl Fn() -> bool,
+) -> Option<Vec<FuzzyMatch<'a>>> {
+    // Perform fuzzy matching per provider and combine
+    let mut matches = vec![];
+    for (provider_idx, haystack) in haystacks.iter().enumerate() {
+        for (chunk_idx, chunk) in haystack.chunks(chunk_size).enumerate() {
+            if is_cancelled() {
+                return None;
+            }
+
+            let offset = (chunk_idx * chunk_size) as u32;
+            let frecency = frecency();
+            matches.extend(
+                fuzzy::fuzzy(
+                    provider_idx.try_into().unwrap(),
+                    line,
+                    cursor_col,
+                    chunk,
+                    frecency.as_ref(),
+                    opts.clone(),
+                )
+                .into_iter()
+                .map(|mut mtch| {
+                    mtch.mtch.index += offset;
+                    mtch
+                }),
+            );
+        }
+    }
+
+    // Collapse duplicates across and within providers
+    if let Some(dedup_opts) = &opts.dedup {
+        matches = dedup::dedup(matches, dedup_opts);
+    }
+
+    // Sort by provider idx then index in haystack
+    matches.sort_by_key(|m| (m.provider_idx, m.mtch.index));
+
+    // Sort by user-defined sorts
+    if let Some(sorts) = &opts.sorts {
+        matches.sort_by(|a, b| {
+            sorts.iter().fold(Ordering::Equal, |acc, sort| {
+                if acc != Ordering::Equal {
+                    return acc;
+                }
+
+                match sort {
+                    // Reverse ordering
+                    Sort::Exact => b.mtch.exact.cmp(&a.mtch.exact),
+                    Sort::Score => b.score.cmp(&a.score),
+                    Sort::Preselect => b.item.preselect.cmp(&a.item.preselect),
+
+                    // Regular ordering
+                    Sort::Kind => a.item.kind.cmp(&b.item.kind),
+                    Sort::SortText => match (&a.item.sort_text, &b.item.sort_text) {
+                        (Some(a), Some(b)) => a.cmp(b),
+                        // Consider results with Some value to be greater than those with None
+                        (Some(_), None) => Ordering::Greater,
+                        (None, Some(_)) => Ordering::Less,
+                        // Neither has sort text
+                        (None, None) => Ordering::Equal,
+                    },
+                    Sort::Label => Sort::label(a.item, b.item),
+                    Sort::Deprecated => a.item.deprecated.cmp(&b.item.deprecated),
+                }
+            })
+        })
+    }
+
+    Some(matches)
+}
+
+impl LuaUserData for Matcher {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method(
+            "set_items",
+            |_, this, (provider_id, items, namespace): (String, Vec<LspItem>, Option<String>)| {
+                recovery::catch_panic(|| {
+                    let namespace = namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+                    this.items_mut().set(namespace, &provider_id, items);
+                    Ok(true)
+                })
+            },
+        );
+        methods.add_method(
+            "fuzzy",
+            |_,
+             this,
+             (line, cursor_col, provider_ids, opts): (
+                mlua::String,
+                usize,
+                Vec<String>,
+                FuzzyOptions,
+            )| {
+                recovery::catch_panic(|| {
+                    Ok(this.fuzzy(&line.to_string_lossy(), cursor_col, &provider_ids, opts)?)
+                })
+            },
+        );
+        methods.add_method(
+            "matched_indices",
+            |_, _, args: fuzzy::MatchedIndicesArgs| {
+                recovery::catch_panic(|| Ok(fuzzy::fuzzy_matched_indices_from_lua(args)))
+            },
+        );
+        methods.add_method(
+            "fuzzy_async",
+            |_,
+             this,
+             (line, cursor_col, provider_ids, opts): (
+                mlua::String,
+                usize,
+                Vec<String>,
+                FuzzyOptions,
+            )| {
+                recovery::catch_panic(|| {
+                    Ok(this.fuzzy_async(line.to_string_lossy(), cursor_col, &provider_ids, opts)?)
+                })
+            },
+        );
+        methods.add_method("access", |_, this, item: LspItem| {
+            recovery::catch_panic(|| {
+                this.access(&item)?;
+                Ok(true)
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(labels: &[&str]) -> Vec<LspItem> {
+        labels
+            .iter()
+            .map(|label| LspItem {
+                label: label.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn opts() -> FuzzyOptions {
+        FuzzyOptions {
+            match_suffix: false,
+            max_typos: 0,
+            use_frecency: false,
+            use_proximity: false,
+            nearby_words: None,
+            boosts: None,
+            max_boost: None,
+            snippet_score_offset: 0,
+            deprecated_score_offset: 0,
+            sorts: None,
+            keyword_class: Default::default(),
+            offset_encodings: Default::default(),
+            dedup: None,
+            filter: None,
+            namespace: None,
+        }
+    }
+
+    #[test]
+    fn test_match_haystacks_in_chunks() {
+        let haystack = items(&["foo", "bar", "foobar", "baz", "barfoo"]);
+        let indices = |chunk_size: usize| {
+            match_haystacks(
+                "foo",
+                3,
+                &[&haystack],
+                || &None,
+                &opts(),
+                chunk_size,
+                || false,
+            )
+            .map(|matches| matches.iter().map(|m| m.mtch.index).collect::<Vec<_>>())
+        };
+        assert_eq!(indices(usize::MAX), Some(vec![0, 2, 4]));
+        assert_eq!(indices(2), Some(vec![0, 2, 4]));
+
+        let matches = match_haystacks("foo", 3, &[&haystack], || &None, &opts(), 2, || true);
+        assert!(matches.is_none());
+    }
+
+    #[test]
+    fn test_new_rejects_shared_and_owned_frecency() {
+        let opts = MatcherOptions {
+            db_path: Some("/nonexistent/frecency.db".to_string()),
+            share_frecency: true,
+        };
+        assert!(matches!(
+            Matcher::new(&opts, Default::default()),
+            Err(Error::InvalidMatcherOptions { .. })
+        ));
+
+        let shared = SharedFrecency::default();
+        let opts = MatcherOptions {
+            db_path: None,
+            share_frecency: true,
+        };
+        let matcher = Matcher::new(&opts, shared.clone()).unwrap();
+        assert!(Arc::ptr_eq(&matcher.frecency_handle(), &shared));
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let matcher = Matcher::default();
+        matcher
+            .items_mut()
+            .set(DEFAULT_NAMESPACE, "lsp", items(&["foo", "bar"]));
+        matcher
+            .items_mut()
+            .set(DEFAULT_NAMESPACE, "buffer", items(&["baz", "foobar"]));
+
+        let provider_ids = ["lsp".to_string(), "buffer".to_string()];
+        let (provider_idxs, indices, _, exacts, merged) =
+            matcher.fuzzy("foo", 3, &provider_ids, opts()).unwrap();
+        let mut matches = provider_idxs.into_iter().zip(indices).collect::<Vec<_>>();
+        matches.sort();
+        assert_eq!(matches, vec![(0, 0), (1, 1)]);
+        assert_eq!(exacts.iter().filter(|&&exact| exact).count(), 1);
+        assert!(merged.is_none());
+
+        assert!(matches!(
+            matcher.fuzzy("foo", 3, &["path".to_string()], opts()),
+            Err(Error::FuzzyBeforeSetItems { .. })
+        ));
+    }
+
+    #[test]
+    fn test_matchers_are_independent() {
+        let a = Matcher::default();
+        let b = Matcher::with_frecency(a.frecency_handle());
+        a.items_mut()
+            .set(DEFAULT_NAMESPACE, "lsp", items(&["foo", "bar"]));
+        b.items_mut().set(DEFAULT_NAMESPACE, "lsp", items(&["baz"]));
+
+        let len = |matcher: &Matcher| {
+            matcher
+                .items()
+                .get(DEFAULT_NAMESPACE, "lsp")
+                .map(|items| items.len())
+        };
+        assert_eq!(len(&a), Some(2));
+        assert_eq!(len(&b), Some(1));
+        assert_eq!(len(&Matcher::default()), None);
+
+        assert!(Arc::ptr_eq(&a.frecency_handle(), &b.frecency_handle()));
+        assert!(matches!(
+            b.access(&items(&["foo"])[0]),
+            Err(Error::UseFrecencyBeforeInit)
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+--- Decodes the packed matches returned by `fuzzy_packed`
+local ffi = require('ffi')
+
+ffi.cdef([[
+  typedef struct {
+    uint32_t index;
+    int32_t score;
+    uint16_t provider_idx;
+    uint8_t exact;
+    uint8_t _pad;
+  } blink_cmp_packed_match;
+]])
+
+local match_size = ffi.sizeof('blink_cmp_packed_match')
+local match_ptr = ffi.typeof('const blink_cmp_packed_match *')
+
+local packed = {}
+
+--- @param bytes string
+--- @return number[] provider_idxs, number[] indices, number[] scores, boolean[] exacts
+function packed.decode_matches(bytes)
+  local count = #bytes / match_size
+  local matches = ffi.cast(match_ptr, bytes)
+
+  local provider_idxs, indices, scores, exacts = {}, {}, {}, {}
+  for i = 0, count - 1 do
+    local mtch = matches[i]
+    provider_idxs[i + 1] = mtch.provider_idx
+    indices[i + 1] = mtch.index
+    scores[i + 1] = mtch.score
+    exacts[i + 1] = mtch.exact ~= 0
+  end
+  return provider_idxs, indices, scores, exacts
+end
+
+return packed
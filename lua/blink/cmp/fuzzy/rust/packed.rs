@@ -0,0 +1,263 @@
+//! Packed binary formats for moving items and matches between Lua and Rust, avoiding the
+//! conversion of every item and match to and from a Lua table
+
+use rmpv::Value;
+
+use crate::error::Error;
+use crate::fuzzy::FuzzyMatch;
+use crate::lsp_item::{ItemFields, LspItem};
+
+/// Size of each match in the packed matches, see `encode_matches`
+pub const PACKED_MATCH_SIZE: usize = 12;
+
+/// Decodes a msgpack array of LSP completion items, as encoded by `vim.mpack.encode`
+pub fn decode_items(mut bytes: &[u8]) -> Result<Vec<LspItem>, Error> {
+    let value = rmpv::decode::read_value(&mut bytes)?;
+    let Value::Array(items) = value else {
+        return Err(invalid("expected an array of items"));
+    };
+    items.iter().map(decode_item).collect()
+}
+
+/// Encodes the matches as native endian records of `PACKED_MATCH_SIZE` bytes, matching the C
+/// struct `{ uint32_t index; int32_t score; uint16_t provider_idx; uint8_t exact; uint8_t _; }`
+pub fn encode_matches(matches: &[FuzzyMatch]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(matches.len() * PACKED_MATCH_SIZE);
+    for mtch in matches {
+        bytes.extend_from_slice(&mtch.mtch.index.to_ne_bytes());
+        bytes.extend_from_slice(&mtch.score.to_ne_bytes());
+        bytes.extend_from_slice(&mtch.provider_idx.to_ne_bytes());
+        bytes.push(mtch.mtch.exact as u8);
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidPackedItems {
+        reason: reason.to_string(),
+    }
+}
+
+/// Fields of a msgpack map
+struct PackedFields<'a>(&'a [(Value, Value)]);
+
+impl<'a> PackedFields<'a> {
+    /// Gets the non-nil value of the key in the map
+    fn get(&self, key: &str) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+            .filter(|v| !v.is_nil())
+    }
+}
+
+fn as_map(value: &Value) -> Result<PackedFields<'_>, Error> {
+    value
+        .as_map()
+        .map(|map| PackedFields(map.as_slice()))
+        .ok_or_else(|| invalid("expected a map"))
+}
+
+impl<'a> ItemFields for PackedFields<'a> {
+    type Error = Error;
+
+    fn string(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            Value::String(s) => Some(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+            Value::Binary(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None,
+        }
+    }
+
+    fn u32(&self, key: &str) -> Result<Option<u32>, Error> {
+        self.get(key)
+            .map(|value| {
+                // Lua numbers without a fractional part, i.e. `15.0`, are encoded as floats
+                let float = value
+                    .as_f64()
+                    .filter(|n| n.fract() == 0. && *n >= 0. && *n <= u32::MAX as f64)
+                    .map(|n| n as u64);
+                value
+                    .as_u64()
+                    .or(float)
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| invalid(&format!("expected {} to be an unsigned integer", key)))
+            })
+            .transpose()
+    }
+
+    fn i32(&self, key: &str) -> Option<i32> {
+        self.get(key)
+            .and_then(|v| v.as_i64())
+            .and_then(|n| i32::try_from(n).ok())
+    }
+
+    fn bool(&self, key: &str) -> bool {
+        self.get(key).and_then(|v| v.as_bool()) == Some(true)
+    }
+
+    fn u32_list(&self, key: &str) -> Vec<u32> {
+        self.get(key)
+            .and_then(|v| v.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|v| v.as_u64())
+                    .filter_map(|n| u32::try_from(n).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn string_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key)?
+            .as_array()?
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => Some(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Self>, Error> {
+        self.get(key).map(as_map).transpose()
+    }
+}
+
+fn decode_item(value: &Value) -> Result<LspItem, Error> {
+    Ok(LspItem::from_fields(&as_map(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_item::{CompletionItemKind, LabelDetails, LspPosition};
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        rmpv::encode::write_value(&mut bytes, value).unwrap();
+        bytes
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    fn position(line: u32, character: u32) -> Value {
+        map(vec![("line", line.into()), ("character", character.into())])
+    }
+
+    #[test]
+    fn test_decode_items() {
+        let items = Value::Array(vec![
+            map(vec![
+                ("label", "foo".into()),
+                ("kind", 15.into()),
+                ("insertTextFormat", 2.into()),
+                ("tags", Value::Array(vec![1.into()])),
+                ("score_offset", (-3).into()),
+                (
+                    "textEdit",
+                    map(vec![
+                        ("newText", "foo(${1:x})".into()),
+                        (
+                            "range",
+                            map(vec![("start", position(0, 2)), ("end", position(0, 4))]),
+                        ),
+                    ]),
+                ),
+            ]),
+            map(vec![
+                ("label", "bar".into()),
+                ("preselect", true.into()),
+                ("detail", "fn bar()".into()),
+                (
+                    "labelDetails",
+                    map(vec![("description", "crate::bar".into())]),
+                ),
+                (
+                    "commitCharacters",
+                    Value::Array(vec![".".into(), "(".into()]),
+                ),
+            ]),
+        ]);
+
+        let items = decode_items(&encode(&items)).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].label, "foo");
+        assert_eq!(items[0].kind, CompletionItemKind::Snippet);
+        assert_eq!(items[0].snippet_prefix.as_deref(), Some("foo(x)"));
+        assert!(items[0].deprecated);
+        assert_eq!(items[0].score_offset, -3);
+        assert_eq!(
+            items[0].text_edit_ranges.map(|ranges| ranges.insert.start),
+            Some(LspPosition {
+                line: 0,
+                character: 2
+            })
+        );
+        assert!(items[1].preselect);
+        assert!(!items[1].deprecated);
+        assert_eq!(items[0].detail, None);
+        assert_eq!(items[1].detail.as_deref(), Some("fn bar()"));
+        assert_eq!(
+            items[1].label_details,
+            Some(LabelDetails {
+                detail: None,
+                description: Some("crate::bar".to_string()),
+            })
+        );
+        assert_eq!(
+            items[1].commit_characters,
+            Some(vec![".".to_string(), "(".to_string()])
+        );
+
+        // Invalid kinds fall back to the default rather than failing every item
+        let items = Value::Array(vec![
+            map(vec![("label", "a".into()), ("kind", "method".into())]),
+            map(vec![("label", "b".into()), ("kind", 1.5.into())]),
+            map(vec![("label", "c".into()), ("kind", 15.0.into())]),
+        ]);
+        let kinds = decode_items(&encode(&items))
+            .unwrap()
+            .iter()
+            .map(|item| item.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                CompletionItemKind::Unknown(0),
+                CompletionItemKind::Unknown(0),
+                CompletionItemKind::Snippet
+            ]
+        );
+
+        assert!(decode_items(&encode(&map(vec![]))).is_err());
+        assert!(decode_items(&[0xc1]).is_err());
+    }
+
+    #[test]
+    fn test_encode_matches() {
+        let item = LspItem::default();
+        let mtch = FuzzyMatch {
+            provider_idx: 2,
+            mtch: frizbee::Match {
+                score: 0,
+                index: 7,
+                exact: true,
+            },
+            item: &item,
+            score: -5,
+            merged: vec![],
+        };
+        let bytes = encode_matches(&[mtch.clone(), mtch]);
+        assert_eq!(bytes.len(), 2 * PACKED_MATCH_SIZE);
+        assert_eq!(&bytes[0..4], &7u32.to_ne_bytes());
+        assert_eq!(&bytes[4..8], &(-5i32).to_ne_bytes());
+        assert_eq!(&bytes[8..10], &2u16.to_ne_bytes());
+        assert_eq!(bytes[10], 1);
+    }
+}
@@ -0,0 +1,123 @@
+//! Recovery from panics, so that a panic while holding a lock doesn't break every following call
+//! until Neovim restarts
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{LazyLock, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use mlua::prelude::*;
+
+use crate::error::Error;
+
+static STATS: LazyLock<Mutex<RecoveryStats>> =
+    LazyLock::new(|| Mutex::new(RecoveryStats::default()));
+
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryStats {
+    /// Number of times the state behind each lock was reset after a panic poisoned the lock
+    pub recovered_locks: HashMap<&'static str, u64>,
+    /// Number of panics caught before reaching Lua
+    pub panics: u64,
+    pub last_panic: Option<String>,
+}
+
+impl RecoveryStats {
+    pub fn recovered(&self) -> bool {
+        self.panics > 0 || !self.recovered_locks.is_empty()
+    }
+}
+
+impl IntoLua for RecoveryStats {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("recovered", self.recovered())?;
+        tab.set("recovered_locks", self.recovered_locks)?;
+        tab.set("panics", self.panics)?;
+        tab.set("last_panic", self.last_panic)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+pub fn stats() -> RecoveryStats {
+    STATS.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Acquires the write lock. If a panic poisoned the lock, the state is reset with `reset` since
+/// it may have been left half updated
+pub fn write<'a, T>(
+    lock: &'a RwLock<T>,
+    name: &'static str,
+    reset: impl FnOnce(&mut T),
+) -> RwLockWriteGuard<'a, T> {
+    lock.write().unwrap_or_else(|poisoned| {
+        let mut guard = poisoned.into_inner();
+        reset(&mut guard);
+        lock.clear_poison();
+        *STATS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .recovered_locks
+            .entry(name)
+            .or_default() += 1;
+        guard
+    })
+}
+
+/// Acquires the read lock, see `write` for how poisoned locks are recovered
+pub fn read<'a, T>(
+    lock: &'a RwLock<T>,
+    name: &'static str,
+    reset: impl FnOnce(&mut T),
+) -> RwLockReadGuard<'a, T> {
+    if lock.is_poisoned() {
+        drop(write(lock, name, reset));
+    }
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Converts panics into errors, rather than unwinding into Lua
+pub fn catch_panic<R>(f: impl FnOnce() -> LuaResult<R>) -> LuaResult<R> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| Err(panicked(payload).into()))
+}
+
+/// Records the caught panic, returning it as an error
+pub fn panicked(payload: Box<dyn Any + Send>) -> Error {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+
+    let mut stats = STATS.lock().unwrap_or_else(PoisonError::into_inner);
+    stats.panics += 1;
+    stats.last_panic = Some(message.clone());
+
+    Error::Panic { message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover_poisoned_lock() {
+        let lock = RwLock::new(vec![1, 2, 3]);
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut guard = lock.write().unwrap();
+            guard.push(4);
+            panic!("half updated");
+        }));
+        assert!(lock.is_poisoned());
+
+        assert!(read(&lock, "test", |vec| vec.clear()).is_empty());
+        assert!(!lock.is_poisoned());
+        write(&lock, "test", |vec| vec.clear()).push(5);
+        assert_eq!(*read(&lock, "test", |vec| vec.clear()), vec![5]);
+        assert_eq!(stats().recovered_locks.get("test"), Some(&1));
+
+        let result = catch_panic::<()>(|| panic!("oops"));
+        assert!(result.is_err());
+        assert!(stats().recovered());
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use regex::Regex;
+
+use crate::error::Error;
+use crate::recovery;
+
+/// Maximum number of compiled regexes kept in the cache, since patterns may come from user
+/// config and `iskeyword` of every buffer
+const MAX_CACHED_REGEXES: usize = 128;
+
+/// Compiled regexes by pattern, shared across tokenizers and keyword classes
+static REGEX_CACHE: LazyLock<RwLock<RegexCache>> =
+    LazyLock::new(|| RwLock::new(RegexCache::default()));
+
+#[derive(Debug, Default)]
+struct RegexCache {
+    regexes: HashMap<String, CachedRegex>,
+    /// Incremented on every access, used to find the least recently used regex
+    tick: AtomicU64,
+}
+
+#[derive(Debug)]
+struct CachedRegex {
+    regex: Regex,
+    last_used: AtomicU64,
+}
+
+impl RegexCache {
+    fn get(&self, pattern: &str) -> Option<Regex> {
+        let cached = self.regexes.get(pattern)?;
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed);
+        cached.last_used.store(tick, Ordering::Relaxed);
+        Some(cached.regex.clone())
+    }
+
+    fn insert(&mut self, pattern: String, regex: Regex) {
+        if self.regexes.len() >= MAX_CACHED_REGEXES && !self.regexes.contains_key(&pattern) {
+            let least_recently_used = self
+                .regexes
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used.load(Ordering::Relaxed))
+                .map(|(pattern, _)| pattern.clone());
+            if let Some(pattern) = least_recently_used {
+                self.regexes.remove(&pattern);
+            }
+        }
+        let last_used = AtomicU64::new(self.tick.fetch_add(1, Ordering::Relaxed));
+        self.regexes
+            .insert(pattern, CachedRegex { regex, last_used });
+    }
+
+    fn clear(&mut self) {
+        self.regexes.clear();
+    }
+}
+
+/// Gets the compiled regex for the pattern, compiling and caching it if necessary
+pub fn get_regex(pattern: &str) -> Result<Regex, Error> {
+    if let Some(regex) = recovery::read(&REGEX_CACHE, "regex_cache", RegexCache::clear).get(pattern)
+    {
+        return Ok(regex);
+    }
+
+    let regex = Regex::new(pattern).map_err(|err| Error::InvalidRegex {
+        pattern: pattern.to_string(),
+        err,
+    })?;
+    recovery::write(&REGEX_CACHE, "regex_cache", RegexCache::clear)
+        .insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = RegexCache::default();
+        for i in 0..MAX_CACHED_REGEXES {
+            cache.insert(format!("a{i}"), Regex::new(&format!("a{i}")).unwrap());
+        }
+        // Touch the oldest regex so the second oldest is evicted instead
+        assert!(cache.get("a0").is_some());
+        cache.insert("b".to_string(), Regex::new("b").unwrap());
+
+        assert_eq!(cache.regexes.len(), MAX_CACHED_REGEXES);
+        assert!(cache.get("a0").is_some());
+        assert!(cache.get("a1").is_none());
+        assert!(cache.get("b").is_some());
+    }
+}
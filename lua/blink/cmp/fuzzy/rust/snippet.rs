@@ -0,0 +1,1122 @@
+//! Parser and expander for the LSP snippet grammar
+//! <https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#snippet_syntax>
+//!
+//! Like VSCode, parsing never fails: constructs which don't match the grammar, such as an
+//! unterminated placeholder, are treated as literal text.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Write};
+
+use mlua::prelude::*;
+
+use crate::regex_cache::get_regex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnippetNode {
+    Text(String),
+    /// `$1`, `${1}` or `${1/regex/format/options}`
+    Tabstop {
+        index: u32,
+        transform: Option<Transform>,
+    },
+    /// `${1:default}`, where the default may contain other nodes
+    Placeholder {
+        index: u32,
+        children: Vec<SnippetNode>,
+    },
+    /// `${1|one,two,three|}`
+    Choice {
+        index: u32,
+        options: Vec<String>,
+    },
+    /// `$name`, `${name}`, `${name:default}` or `${name/regex/format/options}`
+    Variable {
+        name: String,
+        default: Option<Vec<SnippetNode>>,
+        transform: Option<Transform>,
+    },
+}
+
+/// Regex replacement applied to the value of a tabstop or variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transform {
+    /// JavaScript style regex, with `\/` unescaped
+    pub regex: String,
+    pub format: Vec<FormatItem>,
+    /// JavaScript style regex flags, of which `g`, `i`, `m` and `s` are supported
+    pub options: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatItem {
+    Text(String),
+    /// `$1`, `${1}` or `${1:/upcase}`
+    Group {
+        group: u32,
+        modifier: Option<CaseModifier>,
+    },
+    /// `${1:+if}`, `${1:?if:else}` or `${1:-else}`. When `if_set` is `None`, the value of the
+    /// group is inserted if it matched
+    Conditional {
+        group: u32,
+        if_set: Option<String>,
+        if_unset: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseModifier {
+    Upcase,
+    Downcase,
+    Capitalize,
+    CamelCase,
+    PascalCase,
+}
+
+impl TryFrom<&str> for CaseModifier {
+    type Error = ();
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "upcase" => Ok(CaseModifier::Upcase),
+            "downcase" => Ok(CaseModifier::Downcase),
+            "capitalize" => Ok(CaseModifier::Capitalize),
+            "camelcase" => Ok(CaseModifier::CamelCase),
+            "pascalcase" => Ok(CaseModifier::PascalCase),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CaseModifier {
+    fn name(&self) -> &'static str {
+        match self {
+            CaseModifier::Upcase => "upcase",
+            CaseModifier::Downcase => "downcase",
+            CaseModifier::Capitalize => "capitalize",
+            CaseModifier::CamelCase => "camelcase",
+            CaseModifier::PascalCase => "pascalcase",
+        }
+    }
+
+    fn apply(&self, value: &str) -> String {
+        let capitalize = |word: &str| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        };
+        let words = || {
+            value
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+        };
+
+        match self {
+            CaseModifier::Upcase => value.to_uppercase(),
+            CaseModifier::Downcase => value.to_lowercase(),
+            CaseModifier::Capitalize => capitalize(value),
+            CaseModifier::PascalCase => words().map(capitalize).collect(),
+            CaseModifier::CamelCase => words()
+                .enumerate()
+                .map(|(i, word)| {
+                    if i > 0 {
+                        return capitalize(word);
+                    }
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_lowercase().chain(chars).collect::<String>())
+                        .unwrap_or_default()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Maximum depth of nested placeholders and variable defaults. Deeper nodes are kept as literal
+/// text, to avoid overflowing the stack on malicious or malformed snippets
+const MAX_NESTING: usize = 32;
+
+/// Parses the snippet body into its nodes
+pub fn parse(body: &str) -> Vec<SnippetNode> {
+    Parser {
+        src: body,
+        pos: 0,
+        depth: 0,
+        closers: body.match_indices('}').map(|(idx, _)| idx).collect(),
+    }
+    .parse_any(false)
+    .unwrap_or_default()
+}
+
+/// Extracts the text inserted by the snippet up to the first tabstop, choice or variable,
+/// expanding placeholders to their default text.
+///
+/// For example, `foo(${1:bar}, $2)` results in `foo(bar, `
+pub fn literal_prefix(body: &str) -> String {
+    fn push_literal(nodes: &[SnippetNode], out: &mut String) -> bool {
+        for node in nodes {
+            match node {
+                SnippetNode::Text(text) => out.push_str(text),
+                SnippetNode::Placeholder { children, .. } => {
+                    if !push_literal(children, out) {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    let mut prefix = String::new();
+    push_literal(&parse(body), &mut prefix);
+    prefix
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    /// Byte offset of the next char
+    pos: usize,
+    /// Number of `${` currently open
+    depth: usize,
+    /// Byte offsets of every `}`, used to bail out early when the open `${` can't all be closed
+    closers: Vec<usize>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            return true;
+        }
+        false
+    }
+
+    fn parse_int(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        match self.src[start..self.pos].parse() {
+            Ok(int) => Some(int),
+            Err(_) => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    fn parse_var_name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        Some(self.src[start..self.pos].to_string())
+    }
+
+    /// Parses text with escapes until one of the `terminators`, which is consumed. Backslashes
+    /// only escape the chars in `escapable`, and are kept as is otherwise. Returns `None` when
+    /// reaching the end of the input
+    fn parse_escaped_text(&mut self, terminators: &[char], escapable: &[char]) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.bump()? {
+                '\\' => match self.peek() {
+                    Some(c) if escapable.contains(&c) => {
+                        self.bump();
+                        text.push(c);
+                    }
+                    _ => text.push('\\'),
+                },
+                c if terminators.contains(&c) => return Some(text),
+                c => text.push(c),
+            }
+        }
+    }
+
+    /// Parses nodes until the end of the input or, when `nested`, until an unescaped `}`.
+    /// Returns `None` when `nested` and the closing `}` is missing
+    fn parse_any(&mut self, nested: bool) -> Option<Vec<SnippetNode>> {
+        let mut nodes = vec![];
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None if nested => return None,
+                None => break,
+                Some('}') if nested => {
+                    self.bump();
+                    break;
+                }
+                Some('\\') => {
+                    self.bump();
+                    match self.peek() {
+                        Some(c @ ('$' | '}' | '\\')) => {
+                            self.bump();
+                            text.push(c);
+                        }
+                        _ => text.push('\\'),
+                    }
+                }
+                Some('$') => {
+                    let start = self.pos;
+                    match self.parse_dollar() {
+                        Some(node) => {
+                            if !text.is_empty() {
+                                nodes.push(SnippetNode::Text(std::mem::take(&mut text)));
+                            }
+                            nodes.push(node);
+                        }
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                Some(c) => {
+                    self.bump();
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(SnippetNode::Text(text));
+        }
+        Some(nodes)
+    }
+
+    /// Parses a tabstop, placeholder, choice or variable starting at a `$`
+    fn parse_dollar(&mut self) -> Option<SnippetNode> {
+        self.bump();
+        if let Some(index) = self.parse_int() {
+            return Some(SnippetNode::Tabstop {
+                index,
+                transform: None,
+            });
+        }
+        if let Some(name) = self.parse_var_name() {
+            return Some(SnippetNode::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+        if self.depth >= MAX_NESTING || !self.eat('{') {
+            return None;
+        }
+        // Otherwise, unclosed `${` would be retried from every position, taking exponential time
+        let closers_after = self.closers.len() - self.closers.partition_point(|&c| c < self.pos);
+        if closers_after <= self.depth {
+            return None;
+        }
+
+        self.depth += 1;
+        let node = self.parse_braced();
+        self.depth -= 1;
+        node
+    }
+
+    /// Parses a tabstop, placeholder, choice or variable after the opening `${`
+    fn parse_braced(&mut self) -> Option<SnippetNode> {
+        if let Some(index) = self.parse_int() {
+            return match self.bump()? {
+                '}' => Some(SnippetNode::Tabstop {
+                    index,
+                    transform: None,
+                }),
+                ':' => Some(SnippetNode::Placeholder {
+                    index,
+                    children: self.parse_any(true)?,
+                }),
+                '|' => Some(SnippetNode::Choice {
+                    index,
+                    options: self.parse_choice_options()?,
+                }),
+                '/' => Some(SnippetNode::Tabstop {
+                    index,
+                    transform: Some(self.parse_transform()?),
+                }),
+                _ => None,
+            };
+        }
+
+        let name = self.parse_var_name()?;
+        match self.bump()? {
+            '}' => Some(SnippetNode::Variable {
+                name,
+                default: None,
+                transform: None,
+            }),
+            ':' => Some(SnippetNode::Variable {
+                name,
+                default: Some(self.parse_any(true)?),
+                transform: None,
+            }),
+            '/' => Some(SnippetNode::Variable {
+                name,
+                default: None,
+                transform: Some(self.parse_transform()?),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses `one,two|}`, after the opening `${1|`
+    fn parse_choice_options(&mut self) -> Option<Vec<String>> {
+        let mut options = vec![];
+        loop {
+            let option = self.parse_escaped_text(&[',', '|'], &['$', '}', '\\', ',', '|'])?;
+            options.push(option);
+            // The terminator has been consumed, so look back at it
+            if self.src[..self.pos].ends_with('|') {
+                return self.eat('}').then_some(options);
+            }
+        }
+    }
+
+    /// Parses `regex/format/options}`, after the opening `${1/`
+    fn parse_transform(&mut self) -> Option<Transform> {
+        let mut regex = String::new();
+        loop {
+            match self.bump()? {
+                '\\' => match self.bump()? {
+                    '/' => regex.push('/'),
+                    c => {
+                        regex.push('\\');
+                        regex.push(c);
+                    }
+                },
+                '/' => break,
+                c => regex.push(c),
+            }
+        }
+
+        let format = self.parse_format()?;
+
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let options = self.src[start..self.pos].to_string();
+        if !self.eat('}') {
+            return None;
+        }
+
+        Some(Transform {
+            regex,
+            format,
+            options,
+        })
+    }
+
+    /// Parses the format string of a transform until the unescaped `/`
+    fn parse_format(&mut self) -> Option<Vec<FormatItem>> {
+        let mut items = vec![];
+        let mut text = String::new();
+        loop {
+            match self.peek()? {
+                '/' => {
+                    self.bump();
+                    break;
+                }
+                '\\' => {
+                    self.bump();
+                    match self.peek() {
+                        Some(c @ ('$' | '\\' | '/')) => {
+                            self.bump();
+                            text.push(c);
+                        }
+                        _ => text.push('\\'),
+                    }
+                }
+                '$' => {
+                    let start = self.pos;
+                    match self.parse_format_group() {
+                        Some(item) => {
+                            if !text.is_empty() {
+                                items.push(FormatItem::Text(std::mem::take(&mut text)));
+                            }
+                            items.push(item);
+                        }
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                c => {
+                    self.bump();
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            items.push(FormatItem::Text(text));
+        }
+        Some(items)
+    }
+
+    /// Parses a group reference or conditional in a format string, starting at a `$`
+    fn parse_format_group(&mut self) -> Option<FormatItem> {
+        self.bump();
+        if let Some(group) = self.parse_int() {
+            return Some(FormatItem::Group {
+                group,
+                modifier: None,
+            });
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        let group = self.parse_int()?;
+        match self.bump()? {
+            '}' => {
+                return Some(FormatItem::Group {
+                    group,
+                    modifier: None,
+                })
+            }
+            ':' => {}
+            _ => return None,
+        }
+
+        const ESCAPABLE: &[char] = &['$', '}', '\\', ':', '/'];
+        let conditional = |if_set, if_unset| FormatItem::Conditional {
+            group,
+            if_set,
+            if_unset,
+        };
+        match self.peek()? {
+            '+' => {
+                self.bump();
+                let if_set = self.parse_escaped_text(&['}'], ESCAPABLE)?;
+                Some(conditional(Some(if_set), None))
+            }
+            '?' => {
+                self.bump();
+                let if_set = self.parse_escaped_text(&[':'], ESCAPABLE)?;
+                let if_unset = self.parse_escaped_text(&['}'], ESCAPABLE)?;
+                Some(conditional(Some(if_set), Some(if_unset)))
+            }
+            '-' => {
+                self.bump();
+                let if_unset = self.parse_escaped_text(&['}'], ESCAPABLE)?;
+                Some(conditional(None, Some(if_unset)))
+            }
+            _ => {
+                // `${1:/upcase}`, otherwise `${1:else}`
+                let start = self.pos;
+                if self.eat('/') {
+                    if let Some(modifier) = self
+                        .parse_var_name()
+                        .and_then(|name| CaseModifier::try_from(name.as_str()).ok())
+                        .filter(|_| self.eat('}'))
+                    {
+                        return Some(FormatItem::Group {
+                            group,
+                            modifier: Some(modifier),
+                        });
+                    }
+                    self.pos = start;
+                }
+                let if_unset = self.parse_escaped_text(&['}'], ESCAPABLE)?;
+                Some(conditional(None, Some(if_unset)))
+            }
+        }
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, text: &str, escaped: &[char]) -> fmt::Result {
+    for c in text.chars() {
+        if c == '\\' || escaped.contains(&c) {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    Ok(())
+}
+
+fn write_nodes(f: &mut fmt::Formatter, nodes: &[SnippetNode]) -> fmt::Result {
+    nodes.iter().try_for_each(|node| write!(f, "{}", node))
+}
+
+/// Writes the node back in the snippet syntax, such that parsing it results in the same node
+impl Display for SnippetNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnippetNode::Text(text) => write_escaped(f, text, &['$', '}']),
+            SnippetNode::Tabstop { index, transform } => match transform {
+                Some(transform) => write!(f, "${{{}{}}}", index, transform),
+                None => write!(f, "${{{}}}", index),
+            },
+            SnippetNode::Placeholder { index, children } => {
+                write!(f, "${{{}:", index)?;
+                write_nodes(f, children)?;
+                f.write_char('}')
+            }
+            SnippetNode::Choice { index, options } => {
+                write!(f, "${{{}|", index)?;
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_escaped(f, option, &['$', '}', ',', '|'])?;
+                }
+                f.write_str("|}")
+            }
+            SnippetNode::Variable {
+                name,
+                default,
+                transform,
+            } => {
+                write!(f, "${{{}", name)?;
+                if let Some(default) = default {
+                    f.write_char(':')?;
+                    write_nodes(f, default)?;
+                } else if let Some(transform) = transform {
+                    write!(f, "{}", transform)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char('/')?;
+        let mut chars = self.regex.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    f.write_char('\\')?;
+                    if let Some(c) = chars.next() {
+                        f.write_char(c)?;
+                    }
+                }
+                '/' => f.write_str("\\/")?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('/')?;
+        for item in &self.format {
+            write!(f, "{}", item)?;
+        }
+        write!(f, "/{}", self.options)
+    }
+}
+
+impl Display for FormatItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const ESCAPED: &[char] = &['$', '}', ':', '/'];
+        match self {
+            FormatItem::Text(text) => write_escaped(f, text, &['$', '/']),
+            FormatItem::Group { group, modifier } => match modifier {
+                Some(modifier) => write!(f, "${{{}:/{}}}", group, modifier.name()),
+                None => write!(f, "${{{}}}", group),
+            },
+            FormatItem::Conditional {
+                group,
+                if_set,
+                if_unset,
+            } => {
+                write!(f, "${{{}:", group)?;
+                match (if_set, if_unset) {
+                    (Some(if_set), Some(if_unset)) => {
+                        f.write_char('?')?;
+                        write_escaped(f, if_set, ESCAPED)?;
+                        f.write_char(':')?;
+                        write_escaped(f, if_unset, ESCAPED)?;
+                    }
+                    (Some(if_set), None) => {
+                        f.write_char('+')?;
+                        write_escaped(f, if_set, ESCAPED)?;
+                    }
+                    (None, if_unset) => {
+                        f.write_char('-')?;
+                        write_escaped(f, if_unset.as_deref().unwrap_or_default(), ESCAPED)?;
+                    }
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl Transform {
+    /// Replaces the matches of the regex in the value with the format. Invalid regexes leave the
+    /// value unchanged
+    pub fn apply(&self, value: &str) -> String {
+        let flags = self
+            .options
+            .chars()
+            .filter(|c| matches!(c, 'i' | 'm' | 's'))
+            .collect::<String>();
+        let pattern = if flags.is_empty() {
+            self.regex.clone()
+        } else {
+            format!("(?{}){}", flags, self.regex)
+        };
+        let Ok(regex) = get_regex(&pattern) else {
+            return value.to_string();
+        };
+        let limit = if self.options.contains('g') { 0 } else { 1 };
+
+        regex
+            .replacen(value, limit, |captures: &regex::Captures| {
+                let group = |group: u32| {
+                    captures
+                        .get(group as usize)
+                        .map(|m| m.as_str())
+                        .unwrap_or_default()
+                };
+                self.format
+                    .iter()
+                    .map(|item| match item {
+                        FormatItem::Text(text) => text.clone(),
+                        FormatItem::Group { group: g, modifier } => match modifier {
+                            Some(modifier) => modifier.apply(group(*g)),
+                            None => group(*g).to_string(),
+                        },
+                        FormatItem::Conditional {
+                            group: g,
+                            if_set,
+                            if_unset,
+                        } => match (group(*g), if_set, if_unset) {
+                            ("", _, if_unset) => if_unset.clone().unwrap_or_default(),
+                            (_, Some(if_set), _) => if_set.clone(),
+                            (value, None, _) => value.to_string(),
+                        },
+                    })
+                    .collect::<String>()
+            })
+            .into_owned()
+    }
+}
+
+/// A tabstop in the expanded text, as byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpandedTabstop {
+    pub index: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedSnippet {
+    pub text: String,
+    /// Tabstops in the order they appear in the text, including mirrors and `$0`
+    pub tabstops: Vec<ExpandedTabstop>,
+}
+
+impl IntoLua for ExpandedSnippet {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("text", self.text)?;
+        let tabstops = lua.create_table()?;
+        for tabstop in self.tabstops {
+            let tabstop_tab = lua.create_table()?;
+            tabstop_tab.set("index", tabstop.index)?;
+            tabstop_tab.set("start", tabstop.start)?;
+            tabstop_tab.set("end", tabstop.end)?;
+            tabstops.push(tabstop_tab)?;
+        }
+        tab.set("tabstops", tabstops)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+/// Expands the snippet into the text it inserts. Tabstops sharing an index mirror the default
+/// text of the first placeholder or choice with that index, and variables resolve to the given
+/// value, then their default, and then their name
+pub fn expand(nodes: &[SnippetNode], variables: &HashMap<String, String>) -> ExpandedSnippet {
+    let mut expander = Expander {
+        variables,
+        values: HashMap::new(),
+    };
+    expander.collect_values(nodes);
+
+    let mut expanded = ExpandedSnippet {
+        text: String::new(),
+        tabstops: vec![],
+    };
+    expander.expand(nodes, &mut expanded);
+    expanded
+}
+
+struct Expander<'a> {
+    variables: &'a HashMap<String, String>,
+    /// Default text for each tabstop index
+    values: HashMap<u32, String>,
+}
+
+impl Expander<'_> {
+    fn collect_values(&mut self, nodes: &[SnippetNode]) {
+        for node in nodes {
+            match node {
+                SnippetNode::Placeholder { index, children } => {
+                    if !self.values.contains_key(index) {
+                        // Reserve the index first in case the placeholder contains itself
+                        self.values.insert(*index, String::new());
+                        self.collect_values(children);
+                        let mut expanded = ExpandedSnippet {
+                            text: String::new(),
+                            tabstops: vec![],
+                        };
+                        self.expand(children, &mut expanded);
+                        self.values.insert(*index, expanded.text);
+                    }
+                }
+                SnippetNode::Choice { index, options } => {
+                    self.values
+                        .entry(*index)
+                        .or_insert_with(|| options.first().cloned().unwrap_or_default());
+                }
+                SnippetNode::Variable {
+                    default: Some(default),
+                    ..
+                } => self.collect_values(default),
+                _ => {}
+            }
+        }
+    }
+
+    fn expand(&self, nodes: &[SnippetNode], out: &mut ExpandedSnippet) {
+        for node in nodes {
+            let start = out.text.len();
+            match node {
+                SnippetNode::Text(text) => {
+                    out.text.push_str(text);
+                    continue;
+                }
+                SnippetNode::Tabstop { index, transform } => {
+                    let value = self.values.get(index).map(|s| s.as_str()).unwrap_or("");
+                    match transform {
+                        Some(transform) => out.text.push_str(&transform.apply(value)),
+                        None => out.text.push_str(value),
+                    }
+                }
+                SnippetNode::Placeholder { children, .. } => self.expand(children, out),
+                SnippetNode::Choice { index, .. } => out
+                    .text
+                    .push_str(self.values.get(index).map(|s| s.as_str()).unwrap_or("")),
+                SnippetNode::Variable {
+                    name,
+                    default,
+                    transform,
+                } => {
+                    match (self.variables.get(name), default, transform) {
+                        (Some(value), _, Some(transform)) => {
+                            out.text.push_str(&transform.apply(value))
+                        }
+                        (Some(value), _, None) => out.text.push_str(value),
+                        (None, Some(default), _) => self.expand(default, out),
+                        (None, None, _) => out.text.push_str(name),
+                    }
+                    continue;
+                }
+            }
+
+            let index = match node {
+                SnippetNode::Tabstop { index, .. }
+                | SnippetNode::Placeholder { index, .. }
+                | SnippetNode::Choice { index, .. } => *index,
+                _ => unreachable!(),
+            };
+            // Nested tabstops were pushed first, so keep the tabstops sorted by their start
+            let position = out
+                .tabstops
+                .partition_point(|tabstop| tabstop.start < start);
+            out.tabstops.insert(
+                position,
+                ExpandedTabstop {
+                    index,
+                    start,
+                    end: out.text.len(),
+                },
+            );
+        }
+    }
+}
+
+impl IntoLua for SnippetNode {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        match self {
+            SnippetNode::Text(text) => {
+                tab.set("type", "text")?;
+                tab.set("text", text)?;
+            }
+            SnippetNode::Tabstop { index, transform } => {
+                tab.set("type", "tabstop")?;
+                tab.set("index", index)?;
+                tab.set("transform", transform)?;
+            }
+            SnippetNode::Placeholder { index, children } => {
+                tab.set("type", "placeholder")?;
+                tab.set("index", index)?;
+                tab.set("children", children)?;
+            }
+            SnippetNode::Choice { index, options } => {
+                tab.set("type", "choice")?;
+                tab.set("index", index)?;
+                tab.set("options", options)?;
+            }
+            SnippetNode::Variable {
+                name,
+                default,
+                transform,
+            } => {
+                tab.set("type", "variable")?;
+                tab.set("name", name)?;
+                tab.set("children", default)?;
+                tab.set("transform", transform)?;
+            }
+        }
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+impl IntoLua for Transform {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        tab.set("regex", self.regex)?;
+        tab.set("format", self.format)?;
+        tab.set("options", self.options)?;
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+impl IntoLua for FormatItem {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table()?;
+        match self {
+            FormatItem::Text(text) => {
+                tab.set("type", "text")?;
+                tab.set("text", text)?;
+            }
+            FormatItem::Group { group, modifier } => {
+                tab.set("type", "group")?;
+                tab.set("group", group)?;
+                tab.set("modifier", modifier.map(|m| m.name()))?;
+            }
+            FormatItem::Conditional {
+                group,
+                if_set,
+                if_unset,
+            } => {
+                tab.set("type", "conditional")?;
+                tab.set("group", group)?;
+                tab.set("if_set", if_set)?;
+                tab.set("if_unset", if_unset)?;
+            }
+        }
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn text(s: &str) -> SnippetNode {
+        SnippetNode::Text(s.to_string())
+    }
+
+    fn tabstop(index: u32) -> SnippetNode {
+        SnippetNode::Tabstop {
+            index,
+            transform: None,
+        }
+    }
+
+    fn expand_str(body: &str, variables: &[(&str, &str)]) -> String {
+        let variables = variables
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        expand(&parse(body), &variables).text
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("foo"), "foo");
+        assert_eq!(literal_prefix("foo($1)"), "foo(");
+        assert_eq!(literal_prefix("foo(${1:bar}, $2)"), "foo(bar, ");
+        assert_eq!(
+            literal_prefix("${1:outer ${2:inner}} after$0"),
+            "outer inner after"
+        );
+        assert_eq!(literal_prefix("${1:outer $2}"), "outer ");
+        assert_eq!(literal_prefix("${1|one,two|}"), "");
+        assert_eq!(literal_prefix("${1}foo"), "");
+        assert_eq!(literal_prefix("log($TM_SELECTED_TEXT)"), "log(");
+        assert_eq!(literal_prefix("\\$foo \\} \\\\ \\n"), "$foo } \\ \\n");
+        assert_eq!(literal_prefix("cost: $ 5"), "cost: $ 5");
+        // Not a valid placeholder, so it's all text
+        assert_eq!(literal_prefix("${1:unterminated"), "${1:unterminated");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("a$1${2}${3:b${4:c}}"),
+            vec![
+                text("a"),
+                tabstop(1),
+                tabstop(2),
+                SnippetNode::Placeholder {
+                    index: 3,
+                    children: vec![
+                        text("b"),
+                        SnippetNode::Placeholder {
+                            index: 4,
+                            children: vec![text("c")]
+                        }
+                    ]
+                }
+            ]
+        );
+        assert_eq!(
+            parse("${1|a\\,b,c\\|d|}"),
+            vec![SnippetNode::Choice {
+                index: 1,
+                options: vec!["a,b".to_string(), "c|d".to_string()]
+            }]
+        );
+        assert_eq!(
+            parse("${TM_FILENAME/(.*)\\.(\\w+)/${1:/upcase}${2:?.$2:none}\\//g}"),
+            vec![SnippetNode::Variable {
+                name: "TM_FILENAME".to_string(),
+                default: None,
+                transform: Some(Transform {
+                    regex: "(.*)\\.(\\w+)".to_string(),
+                    format: vec![
+                        FormatItem::Group {
+                            group: 1,
+                            modifier: Some(CaseModifier::Upcase)
+                        },
+                        FormatItem::Conditional {
+                            group: 2,
+                            if_set: Some(".$2".to_string()),
+                            if_unset: Some("none".to_string())
+                        },
+                        FormatItem::Text("/".to_string())
+                    ],
+                    options: "g".to_string()
+                })
+            }]
+        );
+        // Malformed constructs fall back to text
+        assert_eq!(parse("${1|a,b}"), vec![text("${1|a,b}")]);
+        assert_eq!(parse("${1/a/b}"), vec![text("${1/a/b}")]);
+        assert_eq!(parse("$ ${}"), vec![text("$ ${}")]);
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        // Nodes past the limit are kept as text, while shallower nodes are still parsed
+        let body = "${1:".repeat(MAX_NESTING + 1) + "x" + &"}".repeat(MAX_NESTING + 1);
+        let nodes = parse(&body);
+        let mut depth = 0;
+        let mut children = &nodes;
+        while let [SnippetNode::Placeholder {
+            children: inner, ..
+        }, ..] = children.as_slice()
+        {
+            depth += 1;
+            children = inner;
+        }
+        assert_eq!(depth, MAX_NESTING);
+        assert!(matches!(&children[0], SnippetNode::Text(text) if text.starts_with("${1:x")));
+
+        // Deeply nested snippets don't overflow the stack
+        let body = "${1:".repeat(10_000);
+        assert_eq!(parse(&body), vec![text(&body)]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let expanded = expand(&parse("fn ${1:name}($2) {\n\t$0\n} // $1"), &HashMap::new());
+        assert_eq!(expanded.text, "fn name() {\n\t\n} // name");
+        assert_eq!(
+            expanded
+                .tabstops
+                .iter()
+                .map(|t| (t.index, t.start, t.end))
+                .collect::<Vec<_>>(),
+            vec![(1, 3, 7), (2, 8, 8), (0, 13, 13), (1, 19, 23)]
+        );
+
+        assert_eq!(expand_str("${1|one,two|} $1", &[]), "one one");
+        assert_eq!(
+            expand_str("$FOO ${BAR:bar} $BAZ", &[("FOO", "foo")]),
+            "foo bar BAZ"
+        );
+        assert_eq!(
+            expand_str("${1:foo_bar}: ${1/(.*)/${1:/pascalcase}/}", &[]),
+            "foo_bar: FooBar"
+        );
+        assert_eq!(
+            expand_str(
+                "${NAME/(\\w+)(-)?/${1:/capitalize}${2:+ }/g}",
+                &[("NAME", "hello-big-world")]
+            ),
+            "Hello Big World"
+        );
+        assert_eq!(
+            expand_str("${A/^(x)?.*$/${1:?yes:no}/}", &[("A", "abc")]),
+            "no"
+        );
+        assert_eq!(
+            expand_str("${A/FOO/${0:/camelcase}/i}", &[("A", "foo-bar")]),
+            "foo-bar"
+        );
+        assert_eq!(CaseModifier::CamelCase.apply("Foo bar-baz"), "fooBarBaz");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_display_roundtrip(body in "[$\\\\{}:|,/a1?+-]{0,24}") {
+            let nodes = parse(&body);
+            let displayed = nodes.iter().map(|node| node.to_string()).collect::<String>();
+            prop_assert_eq!(parse(&displayed), nodes);
+        }
+
+        #[test]
+        fn prop_parse_never_panics(body in "\\PC{0,48}") {
+            let nodes = parse(&body);
+            expand(&nodes, &HashMap::new());
+        }
+
+        #[test]
+        fn prop_escaped_text_is_literal(value in "\\PC{0,24}") {
+            let nodes = [text(&value)];
+            let displayed = nodes[0].to_string();
+            prop_assert_eq!(literal_prefix(&displayed), value.clone());
+            prop_assert_eq!(expand(&parse(&displayed), &HashMap::new()).text, value);
+        }
+    }
+}
@@ -3,12 +3,17 @@ use std::cmp::Ordering;
 use crate::lsp_item::LspItem;
 
 #[derive(Debug, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum Sort {
     Exact,
     Score,
     Kind,
     SortText,
     Label,
+    /// Non-deprecated items first
+    Deprecated,
+    /// Items marked with `preselect` first
+    Preselect,
 }
 
 impl TryFrom<&String> for Sort {
@@ -21,11 +26,13 @@ impl TryFrom<&String> for Sort {
             "kind" => Ok(Sort::Kind),
             "sort_text" => Ok(Sort::SortText),
             "label" => Ok(Sort::Label),
+            "deprecated" => Ok(Sort::Deprecated),
+            "preselect" => Ok(Sort::Preselect),
             _ => Err(mlua::Error::FromLuaConversionError {
                 from: "string",
                 to: "Sort".to_string(),
                 message: Some(format!(
-                    "Invalid sort: {}. Expected one of: exact, score, kind, sort_text, label",
+                    "Invalid sort: {}. Expected one of: exact, score, kind, sort_text, label, deprecated, preselect",
                     s
                 )),
             }),
@@ -45,9 +52,13 @@ fn swap_case(s: &str, i: usize) -> u8 {
 
 impl Sort {
     pub fn label(a: &LspItem, b: &LspItem) -> Ordering {
+        Sort::compare_labels(&a.label, &b.label)
+    }
+
+    pub fn compare_labels(a: &str, b: &str) -> Ordering {
         // prefer foo_bar over _foo_bar
-        let entry1_under = a.label.find(|c: char| c != '_').unwrap_or(a.label.len());
-        let entry2_under = b.label.find(|c: char| c != '_').unwrap_or(b.label.len());
+        let entry1_under = a.find(|c: char| c != '_').unwrap_or(a.len());
+        let entry2_under = b.find(|c: char| c != '_').unwrap_or(b.len());
 
         match entry1_under.cmp(&entry2_under) {
             Ordering::Greater => return Ordering::Greater,
@@ -57,10 +68,10 @@ impl Sort {
 
         // prefer "a" over "A" and "a" over "b"
         // Compare characters one by one with case flipping
-        let min_len = a.label.len().min(b.label.len());
+        let min_len = a.len().min(b.len());
         for i in 0..min_len {
-            let char_a = swap_case(&a.label, i);
-            let char_b = swap_case(&b.label, i);
+            let char_a = swap_case(a, i);
+            let char_b = swap_case(b, i);
 
             match char_a.cmp(&char_b) {
                 Ordering::Equal => continue,
@@ -68,6 +79,6 @@ impl Sort {
             }
         }
 
-        a.label.len().cmp(&b.label.len())
+        a.len().cmp(&b.len())
     }
 }
@@ -0,0 +1,264 @@
+use std::cmp::Ordering;
+
+use frizbee::Match;
+use mlua::prelude::*;
+
+use crate::sort::Sort;
+
+/// How the case of the needle affects matching. Matching always prefers items with the same case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseMode {
+    /// Match regardless of case
+    #[default]
+    Ignore,
+    /// Only match items with the same case as the needle
+    Respect,
+    /// Respect the case when the needle contains an uppercase character, otherwise ignore it
+    Smart,
+}
+
+impl TryFrom<&String> for CaseMode {
+    type Error = mlua::Error;
+
+    fn try_from(s: &String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "ignore" => Ok(CaseMode::Ignore),
+            "respect" => Ok(CaseMode::Respect),
+            "smart" => Ok(CaseMode::Smart),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "CaseMode".to_string(),
+                message: Some(format!(
+                    "Invalid case: {}. Expected one of: ignore, respect, smart",
+                    s
+                )),
+            }),
+        }
+    }
+}
+
+impl CaseMode {
+    fn is_case_sensitive(&self, needle: &str) -> bool {
+        match self {
+            CaseMode::Ignore => false,
+            CaseMode::Respect => true,
+            CaseMode::Smart => needle.chars().any(|c| c.is_uppercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StringFilterOptions {
+    pub max_typos: u16,
+    pub case: CaseMode,
+    /// Sorts which only apply to completion items, such as `kind`, are ignored
+    pub sorts: Vec<Sort>,
+    /// Include the indices of the matched characters in each string, for highlighting
+    pub matched_indices: bool,
+}
+
+impl Default for StringFilterOptions {
+    fn default() -> Self {
+        StringFilterOptions {
+            max_typos: 0,
+            case: CaseMode::Ignore,
+            sorts: vec![Sort::Score],
+            matched_indices: false,
+        }
+    }
+}
+
+impl FromLua for StringFilterOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(StringFilterOptions::default()),
+            LuaValue::Table(tab) => {
+                let defaults = StringFilterOptions::default();
+                let case = tab
+                    .get::<Option<String>>("case")?
+                    .map(|case| (&case).try_into())
+                    .transpose()?;
+                let sorts = tab
+                    .get::<Option<Vec<String>>>("sorts")?
+                    .map(|sorts| {
+                        sorts
+                            .iter()
+                            .map(|s| s.try_into())
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .transpose()?;
+
+                Ok(StringFilterOptions {
+                    max_typos: tab
+                        .get::<Option<u16>>("max_typos")?
+                        .unwrap_or(defaults.max_typos),
+                    case: case.unwrap_or(defaults.case),
+                    sorts: sorts.unwrap_or(defaults.sorts),
+                    matched_indices: tab
+                        .get::<Option<bool>>("matched_indices")?
+                        .unwrap_or(defaults.matched_indices),
+                })
+            }
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "StringFilterOptions".to_string(),
+                message: None,
+            }),
+        }
+    }
+}
+
+/// Indices into the strings, scores, exacts and, when requested, the matched character indices
+pub type StringFilterResult = (Vec<u32>, Vec<u16>, Vec<bool>, Option<Vec<Vec<usize>>>);
+
+/// Fuzzy matches the needle against arbitrary strings, returning the sorted matches
+pub fn filter(needle: &str, strings: &[String], opts: &StringFilterOptions) -> StringFilterResult {
+    let config = frizbee::Config {
+        max_typos: Some(opts.max_typos),
+        sort: false,
+        ..Default::default()
+    };
+    let case_sensitive = opts.case.is_case_sensitive(needle);
+
+    let mut matches = frizbee::match_list(needle, strings, &config)
+        .into_iter()
+        .map(|mtch| {
+            let indices = (case_sensitive || opts.matched_indices)
+                .then(|| {
+                    frizbee::match_indices(needle, &strings[mtch.index as usize], &config)
+                        .map(|m| m.indices)
+                })
+                .flatten();
+            (mtch, indices)
+        })
+        // Without the indices, the case can't be checked
+        .filter(|(mtch, indices)| {
+            !case_sensitive
+                || indices.as_deref().is_some_and(|indices| {
+                    matches_case(needle, &strings[mtch.index as usize], indices)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    matches.sort_by_key(|(mtch, _)| mtch.index);
+    matches.sort_by(|(a, _), (b, _)| {
+        opts.sorts.iter().fold(Ordering::Equal, |acc, sort| {
+            acc.then_with(|| compare(sort, a, b, strings))
+        })
+    });
+
+    (
+        matches.iter().map(|(mtch, _)| mtch.index).collect(),
+        matches.iter().map(|(mtch, _)| mtch.score).collect(),
+        matches.iter().map(|(mtch, _)| mtch.exact).collect(),
+        opts.matched_indices.then(|| {
+            matches
+                .into_iter()
+                .map(|(_, indices)| indices.unwrap_or_default())
+                .collect()
+        }),
+    )
+}
+
+/// Whether every matched character has the same case as the needle character it matched,
+/// walking the needle to the next character matching regardless of case, so that typos don't
+/// shift the following characters. The indices are in bytes, so indices of the continuation
+/// bytes of multibyte characters are skipped
+fn matches_case(needle: &str, haystack: &str, indices: &[usize]) -> bool {
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    let mut needle = needle.chars();
+    indices
+        .into_iter()
+        .filter(|&idx| haystack.is_char_boundary(idx))
+        .filter_map(|idx| haystack[idx..].chars().next())
+        .all(|h| {
+            // Skipped needle characters are typos, while haystack characters without a needle
+            // character are substitutions and keep the needle in place
+            let mut remaining = needle.clone();
+            match remaining.find(|n| h.to_lowercase().eq(n.to_lowercase())) {
+                Some(n) => {
+                    needle = remaining;
+                    h == n
+                }
+                None => true,
+            }
+        })
+}
+
+fn compare(sort: &Sort, a: &Match, b: &Match, strings: &[String]) -> Ordering {
+    match sort {
+        // Reverse ordering
+        Sort::Exact => b.exact.cmp(&a.exact),
+        Sort::Score => b.score.cmp(&a.score),
+
+        // Regular ordering
+        Sort::Label => Sort::compare_labels(&strings[a.index as usize], &strings[b.index as usize]),
+
+        // Only apply to completion items
+        Sort::Kind | Sort::SortText | Sort::Deprecated | Sort::Preselect => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let haystack = strings(&["barfoo", "foo", "Foobar", "baz"]);
+
+        let (indices, scores, exacts, matched) =
+            filter("foo", &haystack, &StringFilterOptions::default());
+        assert_eq!(indices.len(), 3);
+        assert_eq!(indices[0], 1);
+        assert!(exacts[0]);
+        assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+        assert!(matched.is_none());
+
+        let opts = StringFilterOptions {
+            case: CaseMode::Smart,
+            matched_indices: true,
+            ..Default::default()
+        };
+        let (indices, _, _, matched) = filter("Foo", &haystack, &opts);
+        assert_eq!(indices, vec![2]);
+        assert_eq!(matched, Some(vec![vec![0, 1, 2]]));
+
+        let opts = StringFilterOptions {
+            sorts: vec![Sort::Label],
+            ..Default::default()
+        };
+        let (indices, ..) = filter("foo", &haystack, &opts);
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_filter_case() {
+        let opts = StringFilterOptions {
+            case: CaseMode::Smart,
+            ..Default::default()
+        };
+
+        // Matched characters are compared with the needle character at the same position
+        let (indices, ..) = filter("Aa", &strings(&["aA", "Aa"]), &opts);
+        assert_eq!(indices, vec![1]);
+
+        // Multibyte characters don't split on their continuation bytes
+        let (indices, ..) = filter("Fé", &strings(&["fée", "Fée", "Fëe"]), &opts);
+        assert_eq!(indices, vec![1]);
+    }
+
+    #[test]
+    fn test_matches_case_after_typo() {
+        // The typo "x" doesn't shift the needle characters compared with the following matches
+        assert!(matches_case("fxBar", "fooBar", &[0, 3, 4, 5]));
+        assert!(!matches_case("fxBar", "foobar", &[0, 3, 4, 5]));
+        // Substituted characters keep the needle in place
+        assert!(!matches_case("fxBar", "fzbar", &[0, 1, 2, 3, 4]));
+    }
+}
@@ -0,0 +1,42 @@
+--- Polls the tasks returned by `fuzzy_async` on the event loop
+local task = {}
+
+--- Calls the callback with the same values as `fuzzy` once the task is done. The callback isn't
+--- called when the task is cancelled or fails, and failures are reported via `vim.notify` instead.
+--- Polls every `interval_ms` at first, backing off up to `max_interval_ms` for long running tasks
+--- @param fuzzy_task blink.cmp.FuzzyTask
+--- @param callback fun(provider_idxs: number[], matched_indices: number[], scores: number[], exacts: boolean[], merged?: blink.cmp.FuzzyMergedMatch[][])
+--- @param interval_ms? number (default: 1)
+--- @param max_interval_ms? number (default: 16)
+--- @return fun() cancel Cancels the task and stops polling
+function task.await(fuzzy_task, callback, interval_ms, max_interval_ms)
+  local timer = assert(vim.uv.new_timer())
+  local function stop()
+    if not timer:is_closing() then timer:close() end
+  end
+
+  local delay = interval_ms or 1
+  local function poll()
+    local ok, status, provider_idxs, matched_indices, scores, exacts, merged = pcall(fuzzy_task.poll, fuzzy_task)
+    if ok and status == 'pending' then
+      timer:start(delay, 0, poll)
+      delay = math.min(delay * 2, max_interval_ms or 16)
+      return
+    end
+
+    stop()
+    if not ok then
+      vim.schedule(function() vim.notify('blink.cmp: ' .. tostring(status), vim.log.levels.ERROR) end)
+    elseif status == 'done' then
+      vim.schedule(function() callback(provider_idxs, matched_indices, scores, exacts, merged) end)
+    end
+  end
+  timer:start(0, 0, poll)
+
+  return function()
+    fuzzy_task:cancel()
+    stop()
+  end
+end
+
+return task
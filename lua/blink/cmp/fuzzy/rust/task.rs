@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, OnceLock};
+
+use mlua::prelude::*;
+
+use crate::error::Error;
+use crate::matcher::FuzzyResult;
+
+/// Result sent by the worker thread, `None` when the match was cancelled
+pub type TaskResult = Result<Option<FuzzyResult>, Error>;
+
+enum TaskStatus {
+    Pending(Receiver<TaskResult>),
+    Done(FuzzyResult),
+    Cancelled,
+    Failed(mlua::Error),
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Long-lived thread running the async matches of a matcher one after another, so that typing
+/// doesn't start a thread per keystroke. Started on the first match and stopped once the matcher
+/// and its clones are dropped
+#[derive(Default)]
+pub struct Worker {
+    sender: OnceLock<Sender<Job>>,
+}
+
+impl Worker {
+    pub fn run(&self, job: impl FnOnce() + Send + 'static) -> Result<(), Error> {
+        let sender = self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            std::thread::spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            });
+            sender
+        });
+        sender
+            .send(Box::new(job))
+            .map_err(|_| Error::FuzzyTaskStopped)
+    }
+}
+
+/// Handle to a fuzzy match running on a worker thread, see `Matcher::fuzzy_async`
+pub struct FuzzyTask {
+    pub cancelled: Arc<AtomicBool>,
+    status: RefCell<TaskStatus>,
+}
+
+impl FuzzyTask {
+    /// Creates the task along with the sender for the worker's result. The worker owns the only
+    /// sender, so that the task fails if the worker stops without sending a result
+    pub fn new() -> (Self, Sender<TaskResult>) {
+        let (sender, receiver) = mpsc::channel();
+        let task = FuzzyTask {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            status: RefCell::new(TaskStatus::Pending(receiver)),
+        };
+        (task, sender)
+    }
+
+    /// Cooperatively cancels the match, which stops before matching the next chunk of items
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn update_status(&self) {
+        let mut status = self.status.borrow_mut();
+        let TaskStatus::Pending(receiver) = &*status else {
+            return;
+        };
+        *status = match receiver.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Ok(Ok(Some(result))) => TaskStatus::Done(result),
+            Ok(Ok(None)) => TaskStatus::Cancelled,
+            Ok(Err(err)) => TaskStatus::Failed(err.into()),
+            Err(TryRecvError::Disconnected) => TaskStatus::Failed(Error::FuzzyTaskStopped.into()),
+        };
+    }
+}
+
+impl LuaUserData for FuzzyTask {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // Returns the status ('pending', 'done' or 'cancelled') followed by the same values as
+        // `fuzzy` when done
+        methods.add_method("poll", |lua, this, ()| {
+            this.update_status();
+            match &*this.status.borrow() {
+                TaskStatus::Pending(_) => ("pending",).into_lua_multi(lua),
+                TaskStatus::Cancelled => ("cancelled",).into_lua_multi(lua),
+                TaskStatus::Failed(err) => Err(err.clone()),
+                TaskStatus::Done(result) => {
+                    let mut values = result.clone().into_lua_multi(lua)?;
+                    values.push_front("done".into_lua(lua)?);
+                    Ok(values)
+                }
+            }
+        });
+        methods.add_method("cancel", |_, this, ()| {
+            this.cancel();
+            Ok(true)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_status() {
+        let (task, sender) = FuzzyTask::new();
+        task.update_status();
+        assert!(matches!(&*task.status.borrow(), TaskStatus::Pending(_)));
+
+        sender
+            .send(Ok(Some((vec![0], vec![1], vec![2], vec![false], None))))
+            .unwrap();
+        task.update_status();
+        assert!(matches!(&*task.status.borrow(), TaskStatus::Done(_)));
+
+        let (task, sender) = FuzzyTask::new();
+        task.cancel();
+        sender.send(Ok(None)).unwrap();
+        task.update_status();
+        assert!(matches!(&*task.status.borrow(), TaskStatus::Cancelled));
+
+        // The worker stopped without sending a result
+        let (task, sender) = FuzzyTask::new();
+        drop(sender);
+        task.update_status();
+        assert!(matches!(&*task.status.borrow(), TaskStatus::Failed(_)));
+    }
+
+    #[test]
+    fn test_worker_runs_jobs_in_order() {
+        let worker = Worker::default();
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..3 {
+            let sender = sender.clone();
+            worker.run(move || sender.send(i).unwrap()).unwrap();
+        }
+        drop(sender);
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+}
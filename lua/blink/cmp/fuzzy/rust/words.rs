@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use mlua::prelude::*;
+use regex::Regex;
+
+use crate::regex_cache::get_regex;
+
+const DEFAULT_PATTERN: &str = r"[\p{L}_][\p{L}0-9_\\-]{2,}";
+static DEFAULT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(DEFAULT_PATTERN).unwrap());
+
+/// Controls how text is split into words
+#[derive(Debug, Clone)]
+pub struct Tokenizer {
+    regex: Regex,
+    /// Minimum length of a word in bytes (inclusive)
+    min_length: usize,
+    /// Maximum length of a word in bytes (inclusive)
+    max_length: usize,
+    /// Also emit the sub-words of camelCase, PascalCase, snake_case and kebab-case words
+    split_words: bool,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Tokenizer {
+            regex: DEFAULT_REGEX.clone(),
+            min_length: 0,
+            max_length: 511,
+            split_words: false,
+        }
+    }
+}
+
+impl FromLua for Tokenizer {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        if let Some(tab) = value.as_table() {
+            let default = Tokenizer::default();
+            let regex = match tab.get::<Option<String>>("pattern")? {
+                Some(pattern) => get_regex(&pattern)?,
+                None => default.regex,
+            };
+            let min_length = tab
+                .get::<Option<usize>>("min_length")?
+                .unwrap_or(default.min_length);
+            let max_length = tab
+                .get::<Option<usize>>("max_length")?
+                .unwrap_or(default.max_length);
+            let split_words = tab.get("split_words").unwrap_or_default();
+
+            Ok(Tokenizer {
+                regex,
+                min_length,
+                max_length,
+                split_words,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError {
+                from: "LuaValue",
+                to: "Tokenizer".to_string(),
+                message: None,
+            })
+        }
+    }
+}
+
+impl Tokenizer {
+    /// Finds all the words in the text, including duplicates
+    pub fn find_words<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> {
+        self.regex
+            .find_iter(text)
+            .flat_map(|m| {
+                let word = m.as_str();
+                let sub_words = if self.split_words {
+                    split_sub_words(word)
+                } else {
+                    vec![]
+                };
+                std::iter::once(word).chain(sub_words.into_iter().filter(move |s| *s != word))
+            })
+            .filter(|s| (self.min_length..=self.max_length).contains(&s.len()))
+    }
+}
+
+/// Splits a word into its sub-words on non-alphanumeric characters (i.e. `_`, `-`, `$`, `#`),
+/// lower -> upper case transitions and the end of acronyms,
+/// e.g. `parseHTTPResponse_body` -> `parse`, `HTTP`, `Response`, `body`
+fn split_sub_words(word: &str) -> Vec<&str> {
+    let chars = word.char_indices().collect::<Vec<_>>();
+    let mut sub_words = vec![];
+    let mut start: Option<usize> = None;
+
+    for (i, &(idx, c)) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if let Some(start) = start.take() {
+                sub_words.push(&word[start..idx]);
+            }
+            continue;
+        }
+
+        let Some(sub_word_start) = start else {
+            start = Some(idx);
+            continue;
+        };
+
+        let prev = chars[i - 1].1;
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        let is_boundary = c.is_uppercase()
+            && (prev.is_lowercase()
+                || prev.is_numeric()
+                || (prev.is_uppercase() && next.is_some_and(|n| n.is_lowercase())));
+        if is_boundary {
+            sub_words.push(&word[sub_word_start..idx]);
+            start = Some(idx);
+        }
+    }
+    if let Some(start) = start {
+        sub_words.push(&word[start..]);
+    }
+
+    sub_words
+}
+
+/// Words of a buffer, kept per line so that edits only re-tokenize the changed lines
+#[derive(Debug, Default)]
+pub struct WordIndex {
+    tokenizer: Tokenizer,
+    words_by_line: Vec<Vec<String>>,
+    /// Number of occurrences of each word across all lines
+    counts: HashMap<String, usize>,
+}
+
+impl WordIndex {
+    pub fn new(lines: &[String], tokenizer: Tokenizer) -> Self {
+        let mut index = WordIndex {
+            tokenizer,
+            ..Default::default()
+        };
+        index.update(0, 0, lines);
+        index
+    }
+
+    /// Replaces the lines in `[start_row, old_end_row)` with `new_lines`, matching the semantics
+    /// of `nvim_buf_set_lines` and `nvim_buf_attach`'s `on_lines`
+    pub fn update(&mut self, start_row: usize, old_end_row: usize, new_lines: &[String]) {
+        let start_row = start_row.min(self.words_by_line.len());
+        let old_end_row = old_end_row.clamp(start_row, self.words_by_line.len());
+
+        let new_words_by_line = new_lines
+            .iter()
+            .map(|line| {
+                self.tokenizer
+                    .find_words(line)
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for word in new_words_by_line.iter().flatten() {
+            *self.counts.entry(word.clone()).or_default() += 1;
+        }
+
+        let old_words_by_line = self
+            .words_by_line
+            .splice(start_row..old_end_row, new_words_by_line)
+            .collect::<Vec<_>>();
+        for word in old_words_by_line.iter().flatten() {
+            if let Some(count) = self.counts.get_mut(word) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(word);
+                }
+            }
+        }
+    }
+
+    /// Unique words across the whole buffer
+    pub fn words(&self) -> impl Iterator<Item = &String> {
+        self.counts.keys()
+    }
+
+    /// Unique words within `radius` lines of `cursor_row`, along with the distance of the
+    /// closest line containing the word
+    pub fn nearby_words(&self, cursor_row: usize, radius: usize) -> HashMap<String, usize> {
+        let start_row = cursor_row.saturating_sub(radius);
+        let end_row = cursor_row
+            .saturating_add(radius)
+            .saturating_add(1)
+            .min(self.words_by_line.len());
+
+        let mut distance_by_word: HashMap<String, usize> = HashMap::new();
+        for row in start_row..end_row {
+            let distance = row.abs_diff(cursor_row);
+            for word in &self.words_by_line[row] {
+                distance_by_word
+                    .entry(word.clone())
+                    .and_modify(|d| *d = (*d).min(distance))
+                    .or_insert(distance);
+            }
+        }
+        distance_by_word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sub_words() {
+        assert_eq!(
+            split_sub_words("parseHTTPResponse_body"),
+            vec!["parse", "HTTP", "Response", "body"]
+        );
+        assert_eq!(split_sub_words("kebab-case"), vec!["kebab", "case"]);
+        assert_eq!(split_sub_words("__init__"), vec!["init"]);
+        assert_eq!(split_sub_words("word"), vec!["word"]);
+        assert_eq!(split_sub_words("#main-id"), vec!["main", "id"]);
+        assert_eq!(split_sub_words("$var"), vec!["var"]);
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        let tokenizer = Tokenizer {
+            regex: get_regex(r"[$@#]?[\w-]+").unwrap(),
+            min_length: 2,
+            max_length: 16,
+            split_words: true,
+        };
+        let words = tokenizer
+            .find_words("$var @decorator #main-id 9-lives fooBar x")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            words,
+            vec![
+                "$var",
+                "var",
+                "@decorator",
+                "decorator",
+                "#main-id",
+                "main",
+                "id",
+                "9-lives",
+                "lives",
+                "fooBar",
+                "foo",
+                "Bar"
+            ]
+        );
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sorted_words(index: &WordIndex) -> Vec<String> {
+        let mut words = index.words().cloned().collect::<Vec<_>>();
+        words.sort();
+        words
+    }
+
+    #[test]
+    fn test_word_index_update() {
+        let mut index =
+            WordIndex::new(&lines(&["foo bar", "bar baz", "qux"]), Tokenizer::default());
+        assert_eq!(sorted_words(&index), vec!["bar", "baz", "foo", "qux"]);
+
+        // Remove the first line, "bar" is still referenced by the second line
+        index.update(0, 1, &[]);
+        assert_eq!(sorted_words(&index), vec!["bar", "baz", "qux"]);
+
+        // Replace the (new) first line and insert a line after it
+        index.update(0, 1, &lines(&["hello", "world"]));
+        assert_eq!(index.words_by_line.len(), 3);
+        assert_eq!(sorted_words(&index), vec!["hello", "qux", "world"]);
+
+        // Append past the end
+        index.update(10, 10, &lines(&["hello again"]));
+        assert_eq!(index.words_by_line.len(), 4);
+        assert_eq!(sorted_words(&index), vec!["again", "hello", "qux", "world"]);
+    }
+
+    #[test]
+    fn test_word_index_nearby_words() {
+        let index = WordIndex::new(
+            &lines(&["foo", "bar", "baz", "foo", "qux"]),
+            Tokenizer::default(),
+        );
+        let nearby_words = index.nearby_words(2, 1);
+        assert_eq!(nearby_words.len(), 3);
+        assert_eq!(nearby_words["baz"], 0);
+        assert_eq!(nearby_words["bar"], 1);
+        assert_eq!(nearby_words["foo"], 1);
+    }
+}
@@ -1,7 +1,7 @@
 local sort = {}
 
 --- @param list blink.cmp.CompletionItem[]
---- @param funcs ("label" | "sort_text" | "kind" | "score" | "exact" | blink.cmp.SortFunction)[]
+--- @param funcs blink.cmp.Sort[]
 --- @return blink.cmp.CompletionItem[]
 function sort.sort(list, funcs)
   local sorting_funcs = vim.tbl_map(
@@ -37,6 +37,22 @@ function sort.sort_text(a, b)
   return a.sortText < b.sortText
 end
 
+--- @param item blink.cmp.CompletionItem
+--- @return boolean
+local function is_deprecated(item)
+  return item.deprecated or (item.tags ~= nil and vim.tbl_contains(item.tags, 1)) or false
+end
+
+function sort.deprecated(a, b)
+  local a_deprecated, b_deprecated = is_deprecated(a), is_deprecated(b)
+  if a_deprecated ~= b_deprecated then return b_deprecated end
+end
+
+function sort.preselect(a, b)
+  local a_preselect, b_preselect = a.preselect == true, b.preselect == true
+  if a_preselect ~= b_preselect then return a_preselect end
+end
+
 --- Swaps the case of a single character at index i in string s
 --- @param s string
 --- @param i integer
@@ -2,18 +2,146 @@
 --- @field init_db fun(path: string, use_unsafe_no_lock: boolean)
 --- @field destroy_db fun()
 --- @field access fun(item: blink.cmp.CompletionItem)
---- @field get_words fun(text: string): string[]
---- @field set_provider_items fun(provider_id: string, items: blink.cmp.CompletionItem[])
---- @field fuzzy fun(line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): number[], number[], number[], boolean[]
---- @field fuzzy_matched_indices fun(line: string, cursor_col: number, haystack: string[], match_suffix: boolean): number[][]
---- @field get_keyword_range fun(line: string, col: number, match_suffix: boolean): number, number
---- @field guess_edit_range fun(item: blink.cmp.CompletionItem, line: string, cursor_col: number, match_suffix: boolean): number, number
+--- @field new_matcher? fun(opts?: blink.cmp.FuzzyMatcherOptions): blink.cmp.FuzzyMatcher Creates a matcher with its own provider items. Only available in the Rust implementation
+--- @field get_words fun(text: string, tokenizer?: blink.cmp.FuzzyTokenizer): string[]
+--- @field get_nearby_words fun(lines: string[], cursor_idx: number, tokenizer?: blink.cmp.FuzzyTokenizer): table<string, number>
+--- @field set_buffer_lines? fun(bufnr: number, lines: string[], tokenizer?: blink.cmp.FuzzyTokenizer) Indexes the words of the whole buffer, replacing any existing index. Only available in the Rust implementation
+--- @field update_buffer_lines? fun(bufnr: number, start_row: number, old_end_row: number, new_lines: string[]) Replaces the lines in `[start_row, old_end_row)` (0-indexed) of the index, as passed to `nvim_buf_attach`'s `on_lines`. Fails when the buffer hasn't been indexed. Only available in the Rust implementation
+--- @field clear_buffer_lines? fun(bufnr: number): boolean Only available in the Rust implementation
+--- @field get_buffer_words? fun(bufnr: number): string[] | nil Unique words of the indexed buffer, or nil if the buffer hasn't been indexed. Only available in the Rust implementation
+--- @field get_buffer_nearby_words? fun(bufnr: number, cursor_row: number, radius: number): table<string, number> | nil Same as `get_nearby_words` for the lines within `radius` of the 0-indexed `cursor_row` of the indexed buffer, or nil if the buffer hasn't been indexed. Only available in the Rust implementation
+--- @field set_provider_items fun(provider_id: string, items: blink.cmp.CompletionItem[], namespace?: string)
+--- @field set_provider_items_packed? fun(provider_id: string, items: string, namespace?: string) Sets the items from a `vim.mpack.encode` encoded array. Opt-in, since `fuzzy` sets the items as tables, which may hold values that can't be encoded. Only available in the Rust implementation
+--- @field append_provider_items fun(provider_id: string, items: blink.cmp.CompletionItem[], namespace?: string)
+--- @field remove_provider_items fun(provider_id: string, indices: number[], namespace?: string) Removes the items at the 0-indexed positions, shifting the following items down
+--- @field patch_provider_items fun(provider_id: string, patches: table<number, blink.cmp.CompletionItem>, namespace?: string) Replaces the items at the 0-indexed positions
+--- @field clear_provider_items fun(provider_id: string, namespace?: string)
+--- @field clear_namespace fun(namespace: string) Clears the items of all providers in the namespace
+--- @field clear_all fun() Clears the items of all providers in all namespaces
+--- @field set_item_limits? fun(limits: blink.cmp.FuzzyItemLimits) Caps the items retained across all namespaces and providers, evicting the least recently used providers of other namespaces when setting items, and of the same namespace when matching. Matching evicted providers fails with the 'fuzzy_before_set_items' code. Only available in the Rust implementation
+--- @field memory_stats? fun(): blink.cmp.FuzzyMemoryStats Only available in the Rust implementation
+--- @field fuzzy fun(line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): number[], number[], number[], boolean[], blink.cmp.FuzzyMergedMatch[][] | nil
+--- @field fuzzy_async? fun(line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): blink.cmp.FuzzyTask Same as `fuzzy` but matches on a worker thread, cancelling the previous async match. Await with `blink.cmp.fuzzy.rust.task`. Only available in the Rust implementation
+--- @field fuzzy_packed? fun(line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): string, blink.cmp.FuzzyMergedMatch[][] | nil Same as `fuzzy` with the matches packed into a single string, followed by the merged matches. Decode with `blink.cmp.fuzzy.rust.packed`, which requires LuaJIT. Only available in the Rust implementation
+--- @field filter fun(needle: string, strings: string[], opts?: blink.cmp.FuzzyFilterStringsOptions): number[], number[], boolean[], number[][] | nil Returns the sorted 0-indexed positions of the matching strings, their scores, exacts and, when requested, the matched character indices
+--- @field fuzzy_matched_indices fun(line: string, cursor_col: number, haystack: string[], match_suffix: boolean, keyword?: string | blink.cmp.FuzzyKeywordClass, items?: blink.cmp.CompletionItem[], offset_encodings?: table<number, 'utf-8' | 'utf-16' | 'utf-32'>): number[][] When the items are passed, items with a `textEdit` use the text from the start of its range as the needle (Rust implementation only)
+--- @field get_keyword_range fun(line: string, col: number, match_suffix: boolean, keyword?: string | blink.cmp.FuzzyKeywordClass): number, number
+--- @field guess_edit_range fun(item: blink.cmp.CompletionItem, line: string, cursor_col: number, match_suffix: boolean, keyword?: string | blink.cmp.FuzzyKeywordClass, offset_encoding?: 'utf-8' | 'utf-16' | 'utf-32'): number, number Without an `offset_encoding`, items from LSP clients use utf-16 and all others use utf-8
+--- @field register_keyword_class fun(language: string, keyword_class: blink.cmp.FuzzyKeywordClass)
+--- @field health? fun(): blink.cmp.FuzzyHealth Only available in the Rust implementation
+--- @field error_info fun(err: any): blink.cmp.FuzzyError Converts an error caught with `pcall` into a structured error
+--- @field try blink.cmp.FuzzyImplementation Same functions, but returning `nil, err` instead of raising, with `err` as returned by `error_info`
+
+--- @class blink.cmp.FuzzyHealth
+--- @field version string Crate version
+--- @field git_sha string Commit the library was built from
+--- @field frecency? blink.cmp.FuzzyFrecencyHealth Nil when the frecency database hasn't been initialized
+--- @field frecency_error? string Error from reading the frecency database, i.e. when the file was deleted
+--- @field providers number Number of providers holding items, across all namespaces
+--- @field items number
+--- @field bytes number Approximate memory used by the items
+--- @field fuzzy_timings blink.cmp.FuzzyTiming[] Most recent `fuzzy` calls, most recent first
+--- @field recovery blink.cmp.FuzzyRecovery
+
+--- @class blink.cmp.FuzzyFrecencyHealth
+--- @field path string
+--- @field entries number
+--- @field file_size number In bytes
+--- @field decoded_cleanly boolean Whether every entry decoded, corrupted entries are skipped
+
+--- @class blink.cmp.FuzzyTiming
+--- @field duration_ms number
+--- @field items number Number of items across all providers
+--- @field matches? number Nil when cancelled
+--- @field cancelled boolean
+--- @field async boolean
+
+--- @class blink.cmp.FuzzyRecovery
+--- @field recovered boolean Whether any panic was caught or poisoned lock was recovered
+--- @field recovered_locks table<string, number> Number of times the state behind each lock was reset after a panic poisoned it
+--- @field panics number Number of panics caught and raised as errors with the 'panic' code
+--- @field last_panic? string
+
+--- @class blink.cmp.FuzzyError
+--- @field code string Stable identifier, i.e. 'fuzzy_before_set_items' or 'frecency_io'. Errors from outside of the fuzzy implementation, such as invalid arguments, use 'lua'. The 'acquire_*_lock' codes are reserved, but no longer raised since poisoned locks are recovered
+--- @field message string
+--- @field context table<string, string | number> Fields such as `provider_id`, `namespace` or `path`, depending on the code
+
+--- @class blink.cmp.FuzzyMatcherOptions
+--- @field db_path? string Path of a frecency database owned by the matcher. Can't be combined with `share_frecency`
+--- @field share_frecency? boolean Use the frecency database opened via `init_db` (default: false)
+
+--- Matcher owning its provider items, independent of the module level functions
+--- @class blink.cmp.FuzzyMatcher
+--- @field set_items fun(self: blink.cmp.FuzzyMatcher, provider_id: string, items: blink.cmp.CompletionItem[], namespace?: string)
+--- @field fuzzy fun(self: blink.cmp.FuzzyMatcher, line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): number[], number[], number[], boolean[], blink.cmp.FuzzyMergedMatch[][] | nil
+--- @field matched_indices fun(self: blink.cmp.FuzzyMatcher, line: string, cursor_col: number, haystack: string[], match_suffix: boolean, keyword?: string | blink.cmp.FuzzyKeywordClass, items?: blink.cmp.CompletionItem[], offset_encodings?: table<number, 'utf-8' | 'utf-16' | 'utf-32'>): number[][]
+--- @field fuzzy_async fun(self: blink.cmp.FuzzyMatcher, line: string, cursor_col: number, provider_ids: string[], opts: blink.cmp.FuzzyOptions): blink.cmp.FuzzyTask
+--- @field access fun(self: blink.cmp.FuzzyMatcher, item: blink.cmp.CompletionItem)
+
+--- Fuzzy match running on a worker thread against a snapshot of the provider items
+--- @class blink.cmp.FuzzyTask
+--- @field poll fun(self: blink.cmp.FuzzyTask): 'pending' | 'done' | 'cancelled', number[]?, number[]?, number[]?, boolean[]?, blink.cmp.FuzzyMergedMatch[][]? When done, also returns the same values as `fuzzy`
+--- @field cancel fun(self: blink.cmp.FuzzyTask) Stops the match before the next chunk of items
 
 --- @class blink.cmp.FuzzyOptions
 --- @field match_suffix boolean
 --- @field max_typos number
 --- @field use_frecency boolean
 --- @field use_proximity boolean
---- @field nearby_words string[]
+--- @field nearby_words string[] | table<string, number> Nearby words, optionally mapped to their distance in lines from the cursor
+--- @field boosts? table<string, number> Bonus added to the score of items by `filterText`, falling back to the `label`, the same as `nearby_words`
+--- @field max_boost? number Maximum combined bonus from proximity and `boosts`
 --- @field snippet_score_offset number
+--- @field deprecated_score_offset? number Added to the score of deprecated items
 --- @field sorts? blink.cmp.Sort[]
+--- @field keyword? string | blink.cmp.FuzzyKeywordClass Name of a registered language or the keyword class to use
+--- @field offset_encodings? table<number, 'utf-8' | 'utf-16' | 'utf-32'> Encoding of the columns in the items' `textEdit` ranges by LSP client id. Items without a `client_id` use utf-8, while unknown clients default to utf-16
+--- @field dedup? blink.cmp.FuzzyDedupOptions
+--- @field filter? blink.cmp.FuzzyFilterOptions
+--- @field namespace? string Namespace of the provider items to match against (default: 'default')
+
+--- @class blink.cmp.FuzzyDedupOptions
+--- @field key? 'label' | 'label_kind' | 'insert_text' Items sharing this key are collapsed into one. 'insert_text' removes snippet syntax and falls back to the label (default: 'label')
+--- @field keep? 'score' | 'provider' Keep the highest scoring item or the item from the first provider in `provider_ids` (default: 'score')
+
+--- @class blink.cmp.FuzzyFilterOptions
+--- @field exclude_kinds? lsp.CompletionItemKind[] Items with these kinds are never matched
+--- @field exclude_deprecated? boolean Skip items marked as deprecated, via `deprecated` or the `Deprecated` tag
+--- @field min_score? number Drop matches scoring below this value, after all bonuses and offsets
+--- @field exclude_label? string Regex, skip items whose label matches
+
+--- Note, only `matched_indices` is supported by the Lua implementation, which always sorts by score
+--- @class blink.cmp.FuzzyFilterStringsOptions
+--- @field max_typos? number (default: 0)
+--- @field case? 'ignore' | 'respect' | 'smart' Whether matches must have the same case as the needle. `smart` respects the case when the needle contains an uppercase character (default: 'ignore')
+--- @field sorts? blink.cmp.Sort[] Sorts which only apply to completion items, such as 'kind', are ignored (default: { 'score' })
+--- @field matched_indices? boolean Also return the indices of the matched characters, for highlighting (default: false)
+
+--- @class blink.cmp.FuzzyMergedMatch
+--- @field provider_idx number
+--- @field index number
+
+--- @class blink.cmp.FuzzyItemLimits
+--- @field max_items? number
+--- @field max_bytes? number Approximate memory used by the items
+
+--- @class blink.cmp.FuzzyMemoryStats
+--- @field items number
+--- @field bytes number Approximate memory used by the items
+--- @field max_items? number
+--- @field max_bytes? number
+--- @field evictions number Number of providers evicted to stay within the limits
+--- @field by_namespace table<string, table<string, { items: number, bytes: number }>> Stats by namespace, then provider id
+
+--- Note, this is ignored by the Lua implementation
+--- @class blink.cmp.FuzzyTokenizer
+--- @field pattern? string Regex matching a single word (default: `[\p{L}_][\p{L}0-9_\-]{2,}`)
+--- @field min_length? number Minimum length of a word in bytes (default: 0)
+--- @field max_length? number Maximum length of a word in bytes (default: 511)
+--- @field split_words? boolean Also include the sub-words of camelCase, snake_case and kebab-case words (default: false)
+
+--- Characters making up a keyword. Note, this is ignored by the Lua implementation
+--- @class blink.cmp.FuzzyKeywordClass
+--- @field pattern? string Contents of a regex character class, i.e. `\p{L}0-9_$`
+--- @field iskeyword? string Value in the format of Vim's `iskeyword` option, i.e. `@,48-57,_,192-255`
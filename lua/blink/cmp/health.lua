@@ -32,6 +32,60 @@ function health.report_system()
   end
 end
 
+function health.report_fuzzy()
+  vim.health.start('Fuzzy')
+
+  local fuzzy = require('blink.cmp.fuzzy')
+  if fuzzy.implementation_type ~= 'rust' then
+    vim.health.info('Using the Lua implementation')
+    return
+  end
+
+  local info = fuzzy.implementation.health()
+  vim.health.info(('Version %s (%s)'):format(info.version, info.git_sha))
+  vim.health.info(
+    ('Holding %d items from %d providers (~%d KiB)'):format(info.items, info.providers, math.floor(info.bytes / 1024))
+  )
+
+  if info.frecency then
+    local message = ('Frecency database at %s has %d entries (%d bytes)'):format(
+      info.frecency.path,
+      info.frecency.entries,
+      info.frecency.file_size
+    )
+    if info.frecency.decoded_cleanly then
+      vim.health.ok(message)
+    else
+      vim.health.warn(message .. ' but contains corrupted entries, which were skipped. Consider deleting the file')
+    end
+  elseif info.frecency_error then
+    vim.health.error('Failed to read the frecency database: ' .. info.frecency_error)
+  else
+    vim.health.info('Frecency database is not initialized')
+  end
+
+  if #info.fuzzy_timings > 0 then
+    local durations = vim.tbl_map(
+      function(timing) return ('%.2fms (%d items)'):format(timing.duration_ms, timing.items) end,
+      info.fuzzy_timings
+    )
+    vim.health.info('Recent fuzzy calls: ' .. table.concat(durations, ', '))
+  end
+
+  local recovery = info.recovery
+  if not recovery.recovered then
+    vim.health.ok('No panics or poisoned locks')
+    return
+  end
+
+  for lock, count in pairs(recovery.recovered_locks) do
+    vim.health.warn(('Reset the %s state %d time(s) after a panic poisoned its lock'):format(lock, count))
+  end
+  if recovery.panics > 0 then
+    vim.health.warn(('Caught %d panic(s), last: %s'):format(recovery.panics, recovery.last_panic))
+  end
+end
+
 function health.report_sources()
   vim.health.start('Sources')
 
@@ -75,6 +129,7 @@ end
 
 function health.check()
   health.report_system()
+  health.report_fuzzy()
   health.report_sources()
 end
 
@@ -13,6 +13,7 @@
 --- @field kind_hl? string
 --- @field exact? boolean
 --- @field score? number
+--- @field duplicates? blink.cmp.CompletionItem[] Items merged into this item when `fuzzy.dedup` is enabled
 
 return {
   -- some plugins mutate the vim.lsp.protocol.CompletionItemKind table